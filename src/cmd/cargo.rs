@@ -1,12 +1,81 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
 use anyhow::Result;
+use camino_fs::*;
+use cargo_metadata::cargo_platform::{Cfg, CfgExpr};
+use serde::Serialize;
+
+use crate::conf::{CargoSettings, ColorChoice, Configuration, Target};
+use crate::observer::{BuildObserver, no_color};
+
+/// A resolved cargo invocation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CargoCommand {
+    /// The targets built by this invocation
+    pub targets: Vec<Target>,
+    pub args: Vec<String>,
+    /// Environment variables set in addition to the inherited ones
+    pub env: BTreeMap<String, String>,
+}
+
+impl CargoCommand {
+    pub fn run(&self, observer: &dyn BuildObserver) -> Result<()> {
+        let envs = self.env.clone().into_iter().collect::<Vec<_>>();
+        super::run_cargo(&self.args, &envs, observer)
+    }
+}
 
-use crate::conf::Configuration;
+impl fmt::Display for CargoCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.env {
+            write!(f, "{key}={value:?} ")?;
+        }
+        write!(f, "cargo {}", self.args.join(" "))
+    }
+}
 
-pub fn build(conf: &Configuration) -> Result<()> {
+/// The cargo invocations that build the chosen targets: a single one, unless
+/// the targets have different `env`, `rustflags` or feature settings.
+pub fn build_commands(conf: &Configuration) -> Vec<CargoCommand> {
+    let configured = ConfiguredRustflags::load();
+    let mut groups: Vec<(CargoSettings, Vec<Target>)> = vec![];
+    for target in conf.cargo_section.chosen_targets() {
+        let settings = conf.cargo_section.cargo_settings_for(target);
+        match groups.iter_mut().find(|(s, _)| *s == settings) {
+            Some((_, targets)) => targets.push(target),
+            None => groups.push((settings, vec![target])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(settings, targets)| build_command(conf, &configured, &settings, targets))
+        .collect()
+}
+
+/// The cargo invocation that builds `targets` with `settings`.
+fn build_command(
+    conf: &Configuration,
+    configured: &ConfiguredRustflags,
+    settings: &CargoSettings,
+    targets: Vec<Target>,
+) -> CargoCommand {
     let mut args: Vec<String> = vec![];
+    let mut envs = vec![];
 
     args.push("build".into());
-    args.push("--color=always".into());
+    let color = match conf.cli.color.unwrap_or_default() {
+        ColorChoice::Always => "always",
+        ColorChoice::Never => "never",
+        ColorChoice::Auto if no_color() => "never",
+        ColorChoice::Auto => "auto",
+    };
+    args.push(format!("--color={color}"));
+
+    let mut rustflags = remap_path_prefixes(conf);
+    rustflags.extend(settings.rustflags.iter().cloned());
+    add_rustflags(&mut args, &mut envs, configured, &targets, &rustflags);
+    envs.extend(settings.env.clone());
 
     if conf.target_dir != "target" {
         args.push(format!("--target-dir={}", conf.target_dir));
@@ -23,6 +92,10 @@ pub fn build(conf: &Configuration) -> Result<()> {
         args.push(format!("--package={package}"));
     }
 
+    if let Some(jobs) = conf.cli.jobs {
+        args.push(format!("--jobs={jobs}"));
+    }
+
     for _ in 0..conf.cli.verbose {
         args.push("-v".into());
     }
@@ -39,20 +112,422 @@ pub fn build(conf: &Configuration) -> Result<()> {
         args.push(format!("--profile={profile}"));
     }
 
-    if !conf.cli.features.is_empty() {
-        args.push(format!("--features={}", conf.cli.features.join(",")));
+    let (features, default_features) = features(conf, settings);
+    if !features.is_empty() {
+        args.push(format!("--features={}", features.join(",")));
     }
 
     if conf.cli.all_features {
         args.push("--all-features".into());
     }
 
-    if conf.cli.no_default_features {
+    if !default_features {
         args.push("--no-default-features".into());
     }
 
+    for target in &targets {
+        args.push(format!("--target={}", target));
+    }
+    CargoCommand {
+        targets,
+        args,
+        env: envs.into_iter().collect(),
+    }
+}
+
+/// The features a target is built with, the ones of the command line followed
+/// by the configured ones, and whether the default features are enabled.
+pub fn features(conf: &Configuration, settings: &CargoSettings) -> (Vec<String>, bool) {
+    let mut features = conf.cli.features.clone();
+    for feature in &settings.features {
+        if !features.contains(feature) {
+            features.push(feature.clone());
+        }
+    }
+    let no_default_features =
+        conf.cli.no_default_features || settings.no_default_features.unwrap_or(false);
+    (features, !no_default_features)
+}
+
+/// Remove the package's own artifacts so that the next build compiles it again.
+pub fn clean_package(conf: &Configuration) -> Result<()> {
+    let mut args: Vec<String> = vec!["clean".into(), format!("--package={}", conf.package_name)];
+
+    if conf.target_dir != "target" {
+        args.push(format!("--target-dir={}", conf.target_dir));
+    }
+    if let Some(manifest_path) = &conf.cli.manifest_path {
+        args.push(format!("--manifest-path={manifest_path}"));
+    }
+    if conf.cli.release {
+        args.push("--release".into());
+    }
+    if let Some(profile) = &conf.cli.profile {
+        args.push(format!("--profile={profile}"));
+    }
     for target in conf.cargo_section.chosen_targets() {
         args.push(format!("--target={}", target));
     }
-    super::run_cargo(&args, conf.cli.quiet)
+    super::run_cargo(&args, &[], conf.observer.as_ref())
+}
+
+/// Keep the absolute paths of the build machine out of the binaries.
+fn remap_path_prefixes(conf: &Configuration) -> Vec<String> {
+    let mut flags = vec![format!("--remap-path-prefix={}=.", conf.workspace_root)];
+    let cargo_home = std::env::var("CARGO_HOME").ok().or_else(|| {
+        std::env::var("HOME")
+            .ok()
+            .map(|home| format!("{home}/.cargo"))
+    });
+    if let Some(cargo_home) = cargo_home {
+        flags.push(format!("--remap-path-prefix={cargo_home}=/cargo"));
+    }
+    flags
+}
+
+/// Pass extra flags to rustc without discarding the ones already configured.
+///
+/// Cargo only uses one source of rustflags: the environment if set, otherwise
+/// the config files. The environment flags are passed on encoded, so that
+/// flags containing spaces, like a remapped path, stay intact.
+///
+/// In the config files `build.rustflags` is ignored as soon as a
+/// `target.<triple>.rustflags` or `target.<cfg>.rustflags` applies, so the
+/// flags are added to `target.<triple>.rustflags` of every target. Cargo
+/// merges that array with the one of the config files and joins it with the
+/// matching `target.<cfg>.rustflags`. When no target rustflags apply, the
+/// configured `build.rustflags` are carried over as cargo would have used
+/// them.
+fn add_rustflags(
+    args: &mut Vec<String>,
+    envs: &mut Vec<(String, String)>,
+    configured: &ConfiguredRustflags,
+    targets: &[Target],
+    flags: &[String],
+) {
+    let from_env = std::env::var("CARGO_ENCODED_RUSTFLAGS")
+        .map(|encoded| {
+            encoded
+                .split('\x1f')
+                .filter(|f| !f.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        })
+        .or_else(|_| {
+            std::env::var("RUSTFLAGS")
+                .map(|flags| flags.split_whitespace().map(String::from).collect())
+        });
+    match from_env {
+        Ok(mut all) => {
+            all.extend(flags.iter().cloned());
+            envs.push(("CARGO_ENCODED_RUSTFLAGS".into(), all.join("\x1f")));
+        }
+        Err(_) => {
+            for target in targets {
+                let all = configured.target_rustflags(target, flags);
+                let all = serde_json::to_string(&all).expect("serializing strings");
+                args.push(format!("--config=target.{target}.rustflags={all}"));
+            }
+        }
+    }
+}
+
+/// The rustflags of the cargo config files and of the `CARGO_BUILD_RUSTFLAGS`
+/// and `CARGO_TARGET_<TRIPLE>_RUSTFLAGS` variables.
+#[derive(Debug, Default)]
+struct ConfiguredRustflags {
+    /// `build.rustflags`
+    build: Vec<String>,
+    /// The keys of the `target.<triple>` and `target.<cfg>` tables that set
+    /// rustflags
+    targets: Vec<String>,
+}
+
+impl ConfiguredRustflags {
+    /// The rustflags cargo sees when started in the current directory.
+    ///
+    /// Unreadable config files are skipped, cargo reports them itself.
+    fn load() -> Self {
+        let dir = std::env::current_dir()
+            .ok()
+            .and_then(|dir| Utf8PathBuf::from_path_buf(dir).ok())
+            .unwrap_or_default();
+        let cargo_home = std::env::var("CARGO_HOME").ok().or_else(|| {
+            std::env::var("HOME")
+                .ok()
+                .map(|home| format!("{home}/.cargo"))
+        });
+        let mut configured = Self::read(&dir, cargo_home.as_deref().map(Utf8Path::new));
+
+        if let Ok(flags) = std::env::var("CARGO_BUILD_RUSTFLAGS") {
+            configured
+                .build
+                .extend(flags.split_whitespace().map(String::from));
+        }
+        for (key, _) in std::env::vars() {
+            if let Some(triple) = key
+                .strip_prefix("CARGO_TARGET_")
+                .and_then(|key| key.strip_suffix("_RUSTFLAGS"))
+            {
+                configured.targets.push(triple.to_string());
+            }
+        }
+        configured
+    }
+
+    /// Read the config files that cargo discovers from `dir`, the ones
+    /// closer to `dir` taking precedence, and the one of `cargo_home`.
+    fn read(dir: &Utf8Path, cargo_home: Option<&Utf8Path>) -> Self {
+        let mut config_dirs = dir
+            .ancestors()
+            .map(|dir| dir.join(".cargo"))
+            .collect::<Vec<_>>();
+        if let Some(cargo_home) = cargo_home
+            && !config_dirs.iter().any(|dir| dir == cargo_home)
+        {
+            config_dirs.push(cargo_home.to_path_buf());
+        }
+
+        let mut configured = Self::default();
+        // Arrays are joined with the values of higher precedence last
+        for config_dir in config_dirs.iter().rev() {
+            let Some(table) = ["config", "config.toml"]
+                .iter()
+                .map(|name| config_dir.join(name))
+                .find(|path| path.is_file())
+                .and_then(|path| path.read_string().ok())
+                .and_then(|content| content.parse::<toml::Table>().ok())
+            else {
+                continue;
+            };
+            if let Some(flags) = table.get("build").and_then(|b| b.get("rustflags")) {
+                configured.build.extend(rustflags_value(flags));
+            }
+            let targets = table.get("target").and_then(toml::Value::as_table);
+            for (key, target) in targets.into_iter().flatten() {
+                if target.get("rustflags").is_some() && !configured.targets.contains(key) {
+                    configured.targets.push(key.clone());
+                }
+            }
+        }
+        configured
+    }
+
+    /// The `target.<triple>.rustflags` to pass for `target` so that cargo
+    /// uses `flags` in addition to the configured ones.
+    fn target_rustflags(&self, target: &Target, flags: &[String]) -> Vec<String> {
+        let mut all = if self.target_applies(target) {
+            vec![]
+        } else {
+            self.build.clone()
+        };
+        all.extend(flags.iter().cloned());
+        all
+    }
+
+    /// Whether target rustflags apply to `target`, which makes cargo ignore
+    /// `build.rustflags`.
+    fn target_applies(&self, target: &Target) -> bool {
+        let env_key = target.as_str().to_uppercase().replace(['-', '.'], "_");
+        let mut cfgs = None;
+        self.targets.iter().any(|key| {
+            if key == target.as_str() || *key == env_key {
+                return true;
+            }
+            if !key.starts_with("cfg(") {
+                return false;
+            }
+            let cfgs = cfgs.get_or_insert_with(|| target_cfgs(target));
+            match cfgs {
+                Some(cfgs) => CfgExpr::matches_key(key, cfgs),
+                // Without the cfg values of the target assume the key applies,
+                // as the flags were before
+                None => true,
+            }
+        })
+    }
+}
+
+/// The flags of a rustflags value, which is either an array or a string of
+/// space separated flags.
+fn rustflags_value(value: &toml::Value) -> Vec<String> {
+    match value {
+        toml::Value::String(flags) => flags.split_whitespace().map(String::from).collect(),
+        toml::Value::Array(flags) => flags
+            .iter()
+            .filter_map(|f| f.as_str().map(String::from))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// The cfg values of `target` according to rustc.
+fn target_cfgs(target: &Target) -> Option<Vec<Cfg>> {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let output = std::process::Command::new(rustc)
+        .args(["--print=cfg", &format!("--target={target}")])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::testing::example;
+
+    fn conf(section: serde_json::Value) -> Configuration {
+        example(section).build().unwrap()
+    }
+
+    #[test]
+    fn targets_with_different_settings_get_their_own_invocation() {
+        let section = serde_json::json!({
+            "include-dir": "include",
+            "iOS": true,
+            "simulators": true,
+            "macOS": true,
+        });
+        let commands = build_commands(&conf(section.clone()));
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].targets.len(), 5);
+
+        let mut section = section;
+        section["platform"] = serde_json::json!({
+            "macOS": { "env": { "MACOSX_DEPLOYMENT_TARGET": "11.0" } },
+        });
+        section["target"] = serde_json::json!({
+            "x86_64-apple-ios": { "rustflags": ["-Copt-level=s"] },
+        });
+        let commands = build_commands(&conf(section));
+        let targets = commands
+            .iter()
+            .map(|c| c.targets.iter().map(Target::as_str).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            [
+                vec!["aarch64-apple-ios", "aarch64-apple-ios-sim"],
+                vec!["x86_64-apple-ios"],
+                vec!["x86_64-apple-darwin", "aarch64-apple-darwin"],
+            ]
+        );
+        assert_eq!(commands[2].env["MACOSX_DEPLOYMENT_TARGET"], "11.0");
+        // The flags are added for every target, as build.rustflags is
+        // ignored when the config files set target rustflags
+        let configs = commands[2]
+            .args
+            .iter()
+            .filter(|a| a.starts_with("--config=target."))
+            .map(|a| a.split_once(".rustflags=").unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            configs,
+            [
+                "--config=target.x86_64-apple-darwin",
+                "--config=target.aarch64-apple-darwin"
+            ]
+        );
+        // The remap flags are kept next to the target's own rustflags
+        let rustflags = commands[1]
+            .args
+            .iter()
+            .chain(commands[1].env.values())
+            .find(|a| a.contains("-Copt-level=s"))
+            .unwrap();
+        assert!(rustflags.contains("--remap-path-prefix"), "{rustflags}");
+    }
+
+    #[test]
+    fn configured_build_rustflags_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let project = dir.join("project");
+        dir.join(".cargo/config.toml")
+            .write("[build]\nrustflags = \"-Cforce-frame-pointers=yes\"\n")
+            .unwrap();
+        project
+            .join(".cargo/config.toml")
+            .write("[build]\nrustflags = [\"-Ctarget-cpu=apple-m1\"]\n")
+            .unwrap();
+        project.join("src").mkdirs().unwrap();
+
+        let ios: Target = "aarch64-apple-ios".parse().unwrap();
+        let remap = ["--remap-path-prefix=/ws=.".to_string()];
+        let configured = ConfiguredRustflags::read(&project.join("src"), None);
+        assert_eq!(
+            configured.target_rustflags(&ios, &remap),
+            [
+                "-Cforce-frame-pointers=yes",
+                "-Ctarget-cpu=apple-m1",
+                "--remap-path-prefix=/ws=."
+            ]
+        );
+
+        // Cargo ignores build.rustflags when target rustflags apply, and
+        // merges the passed flags with them
+        dir.join(".cargo/config.toml")
+            .write("[target.'cfg(target_os = \"ios\")']\nrustflags = [\"-Cdebuginfo=1\"]\n")
+            .unwrap();
+        let configured = ConfiguredRustflags::read(&project, None);
+        assert_eq!(configured.target_rustflags(&ios, &remap), remap);
+        let macos: Target = "aarch64-apple-darwin".parse().unwrap();
+        assert_eq!(
+            configured.target_rustflags(&macos, &remap),
+            ["-Ctarget-cpu=apple-m1", "--remap-path-prefix=/ws=."]
+        );
+    }
+
+    #[test]
+    fn features_per_platform_and_target() {
+        let conf = conf(serde_json::json!({
+            "include-dir": "include",
+            "iOS": true,
+            "simulators": true,
+            "macOS": true,
+            "platform": {
+                "iOS": { "features": ["keychain"] },
+                "macOS": { "features": ["launchd"], "no-default-features": true },
+            },
+            "target": {
+                "x86_64-apple-darwin": { "features": ["sse"], "no-default-features": false },
+            },
+        }));
+        let commands = build_commands(&conf);
+        let summary = commands
+            .iter()
+            .map(|c| {
+                let targets = c.targets.iter().map(Target::as_str).collect::<Vec<_>>();
+                let flags = c
+                    .args
+                    .iter()
+                    .filter(|a| a.contains("features"))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (targets.join(" "), flags.join(" "))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("aarch64-apple-ios".into(), "--features=keychain".into()),
+                (
+                    "aarch64-apple-ios-sim x86_64-apple-ios".into(),
+                    String::new()
+                ),
+                (
+                    "x86_64-apple-darwin".into(),
+                    "--features=launchd,sse".into()
+                ),
+                (
+                    "aarch64-apple-darwin".into(),
+                    "--features=launchd --no-default-features".into()
+                ),
+            ]
+        );
+    }
 }
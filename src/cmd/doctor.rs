@@ -0,0 +1,272 @@
+use std::fmt;
+use std::process::Command;
+
+use anyhow::Result;
+use camino_fs::*;
+use serde::Serialize;
+use xshell::Shell;
+
+use crate::Error;
+use crate::conf::{Configuration, ConfigurationBuilder};
+use crate::core::platform::{ApplePlatform, Environment};
+use crate::core::{self, modulemap};
+
+/// The result of checking the build environment.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub section: &'static str,
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Report {
+    fn check(&mut self, section: &'static str, name: impl Into<String>, result: Result<String>) {
+        let (ok, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, format!("{e:#}")),
+        };
+        self.checks.push(Check {
+            section,
+            name: name.into(),
+            ok,
+            detail,
+        });
+    }
+
+    /// The failed checks, as `section name`.
+    pub fn failed(&self) -> Vec<String> {
+        self.checks
+            .iter()
+            .filter(|c| !c.ok)
+            .map(|c| format!("{} {}", c.section, c.name))
+            .collect()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut section = "";
+        for check in &self.checks {
+            if check.section != section {
+                section = check.section;
+                writeln!(f, "{section}")?;
+            }
+            let mark = if check.ok { "✅" } else { "❌" };
+            writeln!(f, "  {mark} {}: {}", check.name, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check the tools, SDKs and targets a build needs. The package
+/// configuration is checked when `manifest_path` (or the current directory)
+/// holds one.
+pub fn diagnose(manifest_path: Option<&Utf8Path>, package: Option<&str>) -> Report {
+    let mut report = Report { checks: vec![] };
+
+    for tool in ["xcrun", "lipo", "plutil", "xcodebuild", "install_name_tool"] {
+        report.check("Tools", tool, find_in_path(tool));
+    }
+
+    report.check("Xcode", "developer dir", developer_dir());
+    report.check(
+        "Xcode",
+        "version",
+        output("xcodebuild", &["-version"]).map(|v| v.lines().next().unwrap_or_default().into()),
+    );
+
+    let mut builder = ConfigurationBuilder::new();
+    if let Some(manifest_path) = manifest_path {
+        builder = builder.manifest_path(manifest_path);
+    }
+    if let Some(package) = package {
+        builder = builder.package(package);
+    }
+    let conf = builder.build();
+
+    let platforms = match &conf {
+        Ok(conf) => conf
+            .cargo_section
+            .platform_targets()
+            .into_iter()
+            .map(|(platform, _)| platform)
+            .collect(),
+        Err(_) => vec![
+            ApplePlatform::IOS(Environment::Device),
+            ApplePlatform::IOS(Environment::Simulator),
+            ApplePlatform::MacOS,
+        ],
+    };
+    let sh = Shell::new().ok();
+    for platform in platforms {
+        let version = sh
+            .as_ref()
+            .map(|sh| core::query_sdk_version(sh, &platform))
+            .unwrap_or_default();
+        let result = if version.is_empty() {
+            Err(anyhow::anyhow!("SDK not found"))
+        } else {
+            Ok(version)
+        };
+        report.check("SDKs", platform.platform_name(), result);
+    }
+
+    let rustc = output("rustc", &["--version"]);
+    let is_nightly = rustc
+        .as_ref()
+        .is_ok_and(|v| v.contains("nightly") || v.contains("-dev"));
+    report.check("Rust", "rustc", rustc);
+
+    let conf = match conf {
+        Ok(conf) => conf,
+        Err(e) => {
+            let e = match e {
+                Error::Config(e) => e,
+                e => e.into(),
+            };
+            report.check("Configuration", "package", Err(e));
+            return report;
+        }
+    };
+
+    let installed =
+        super::rustup::sysroot().map(|sysroot| super::rustup::installed_targets(&sysroot));
+    for target in conf.cargo_section.chosen_targets() {
+        let result = match &installed {
+            Ok(installed) if installed.iter().any(|t| t == target.as_str()) => {
+                Ok("installed".into())
+            }
+            Ok(_) if conf.builds_std() => Ok("built with build-std".into()),
+            Ok(_) => Err(anyhow::anyhow!(
+                "not installed, run `rustup target add {target}`"
+            )),
+            Err(e) => Err(anyhow::anyhow!("{e:#}")),
+        };
+        report.check("Rust", target.as_str(), result);
+    }
+    if conf.builds_std() {
+        let result = if is_nightly {
+            Ok("nightly toolchain found".into())
+        } else {
+            Err(anyhow::anyhow!(
+                "build-std requires a nightly toolchain, run `rustup override set nightly`"
+            ))
+        };
+        report.check("Rust", "build-std", result);
+    }
+
+    report.check("Configuration", "package", Ok(conf.package_name.clone()));
+    let include_dir = &conf.cargo_section.include_dir;
+    let result = if include_dir.is_dir() {
+        Ok(include_dir.to_string())
+    } else {
+        Err(anyhow::anyhow!("{include_dir} doesn't exist"))
+    };
+    report.check("Configuration", "include-dir", result);
+    report.check("Configuration", "modulemap", check_modulemap(&conf));
+    report
+}
+
+fn check_modulemap(conf: &Configuration) -> Result<String> {
+    let module = conf.module_name()?;
+    // Found the same way as by the build
+    let path = crate::plan::get_module_path(&conf.cargo_section.include_dir)?;
+    check_referenced_headers(&path)?;
+    Ok(format!("module {module}"))
+}
+
+/// Check that the headers of a modulemap exist, relative to the modulemap
+/// like clang resolves them.
+fn check_referenced_headers(modulemap: &Utf8Path) -> Result<()> {
+    let dir = modulemap.parent().unwrap_or(Utf8Path::new("."));
+    let missing = modulemap::referenced_headers(&modulemap.read_string()?)
+        .into_iter()
+        .filter(|header| !dir.join(header).exists())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "{modulemap} references missing headers: {}",
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn find_in_path(tool: &str) -> Result<String> {
+    let path = std::env::var("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(tool))
+        .find(|path| path.is_file())
+        .map(|path| path.display().to_string())
+        .ok_or_else(|| anyhow::anyhow!("not found in PATH"))
+}
+
+fn developer_dir() -> Result<String> {
+    if let Ok(dir) = std::env::var("DEVELOPER_DIR") {
+        return Ok(format!("{dir} (from DEVELOPER_DIR)"));
+    }
+    output("xcode-select", &["--print-path"])
+}
+
+/// The trimmed standard output of a successful command.
+fn output(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow::anyhow!("{program}: {e}"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_is_grouped_by_section() {
+        let mut report = Report { checks: vec![] };
+        report.check("Tools", "lipo", Ok("/usr/bin/lipo".into()));
+        report.check("Tools", "plutil", Err(anyhow::anyhow!("not found in PATH")));
+        report.check("Rust", "rustc", Ok("rustc 1.90.0".into()));
+
+        assert_eq!(report.failed(), ["Tools plutil"]);
+        assert_eq!(
+            report.to_string(),
+            "Tools\n  ✅ lipo: /usr/bin/lipo\n  ❌ plutil: not found in PATH\nRust\n  ✅ rustc: rustc 1.90.0\n"
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["checks"][1]["ok"], false);
+    }
+
+    #[test]
+    fn headers_are_resolved_next_to_a_nested_modulemap() {
+        let dir = tempfile::tempdir().unwrap();
+        let include_dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        include_dir.join("mymath.h").write("").unwrap();
+        include_dir
+            .join("MyMath/module.modulemap")
+            .write("module MyMath {\n    header \"mymath.h\"\n}\n")
+            .unwrap();
+
+        let path = crate::plan::get_module_path(&include_dir).unwrap();
+        let err = check_referenced_headers(&path).unwrap_err().to_string();
+        assert!(
+            err.ends_with("references missing headers: mymath.h"),
+            "{err}"
+        );
+
+        include_dir.join("MyMath/mymath.h").write("").unwrap();
+        check_referenced_headers(&path).unwrap();
+    }
+}
@@ -1,52 +1,87 @@
 pub mod cargo;
+pub mod doctor;
 pub mod modulemap;
+pub mod rustup;
 
-use anyhow::{Result, anyhow};
+use crate::observer::{BuildObserver, Event, Stream};
+use anyhow::Result;
+use std::fmt;
 use std::process::Command;
-use yansi::Paint;
 
-fn run_cargo(args: &[String], quiet: bool) -> Result<()> {
-    run("cargo", args, quiet)
+/// A command that exited unsuccessfully.
+#[derive(Debug)]
+pub struct CommandError {
+    pub program: String,
+    pub status: Option<i32>,
+    /// The captured stderr, empty unless the observer captures the output
+    pub stderr: String,
 }
 
-fn run(program: &str, args: &[String], quiet: bool) -> Result<()> {
-    if quiet {
-        return run_quiet(program, args);
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed with status: {:?}", self.program, self.status)
     }
+}
 
-    let mut cmd = Command::new(program).args(args).spawn()?;
-    let status = cmd.wait()?;
-    let cmd = Paint::new(format!("{} {}", program, args.join(" "))).dim();
-    if status.success() {
-        println!("{} done {}", Paint::green(" XCFramework").bold(), cmd);
-        Ok(())
-    } else {
-        println!(
-            "{} error when running: {}",
-            Paint::red(" XCFramework").bold(),
-            cmd
-        );
-        Err(anyhow!("Command failed with status: {:?}", status.code()))
-    }
+impl std::error::Error for CommandError {}
+
+fn run_cargo(
+    args: &[String],
+    envs: &[(String, String)],
+    observer: &dyn BuildObserver,
+) -> Result<()> {
+    run("cargo", args, envs, observer)
 }
 
-fn run_quiet(program: &str, args: &[String]) -> Result<()> {
-    let output = Command::new(program).args(args).output()?;
+fn run(
+    program: &str,
+    args: &[String],
+    envs: &[(String, String)],
+    observer: &dyn BuildObserver,
+) -> Result<()> {
+    observer.event(&Event::CommandSpawned {
+        program: program.into(),
+        args: args.to_vec(),
+    });
+    let mut cmd = Command::new(program);
+    cmd.args(args).envs(envs.iter().cloned());
+
+    let (status, stderr) = if observer.captures_output() {
+        let output = cmd.output()?;
+        for (stream, bytes) in [
+            (Stream::Stdout, &output.stdout),
+            (Stream::Stderr, &output.stderr),
+        ] {
+            for line in String::from_utf8_lossy(bytes).lines() {
+                observer.event(&Event::CommandOutput {
+                    program: program.into(),
+                    stream,
+                    line: line.into(),
+                });
+            }
+        }
+        (
+            output.status,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    } else {
+        (cmd.spawn()?.wait()?, String::new())
+    };
 
-    let cmd = Paint::new(format!("{} {}", program, args.join(" "))).dim();
-    if output.status.success() {
+    observer.event(&Event::CommandFinished {
+        program: program.into(),
+        args: args.to_vec(),
+        status: status.code(),
+        success: status.success(),
+    });
+    if status.success() {
         Ok(())
     } else {
-        println!(
-            "{} error when running: {}",
-            Paint::red(" XCFramework").bold(),
-            cmd
-        );
-        eprintln!("stderr: {}", String::from_utf8_lossy(&output.stderr));
-        println!("stdout: {}", String::from_utf8_lossy(&output.stdout));
-        Err(anyhow!(
-            "Command failed with status: {:?}",
-            output.status.code()
-        ))
+        Err(CommandError {
+            program: program.into(),
+            status: status.code(),
+            stderr,
+        }
+        .into())
     }
 }
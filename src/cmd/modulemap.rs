@@ -1,6 +1,7 @@
 use std::io;
 
 use crate::conf::Configuration;
+use crate::core::modulemap::parse_module_name;
 use anyhow::{Context, Result, bail};
 use camino_fs::*;
 use std::fs::File;
@@ -23,25 +24,6 @@ pub fn get_module_name(conf: &Configuration) -> Result<String> {
     ))
 }
 
-fn parse_module_name(content: &str) -> Result<String> {
-    let found_start = content.lines().find_map(|line| {
-        line.strip_prefix("framework module ")
-            .or_else(|| line.strip_prefix("module "))
-    });
-
-    let Some(found_start) = found_start else {
-        bail!("No 'module' declaration found");
-    };
-
-    let mut module = found_start.trim_end();
-    if module.ends_with('{') {
-        module = module[..module.len() - 1].trim_end();
-    } else {
-        bail!("Expected `module <name> {{` not `{module}`");
-    }
-    Ok(module.to_string())
-}
-
 fn ls_modulemap_files(dir: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
     Ok(dir
         .ls()
@@ -0,0 +1,112 @@
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+use camino_fs::*;
+
+use crate::conf::{Configuration, Target};
+use crate::observer::{Event, Stage};
+
+/// Check that the standard library of every chosen target is installed, and
+/// install the missing ones with rustup when `--install-targets` is passed.
+///
+/// Without this check a missing target either fails with a cryptic
+/// "can't find crate for `core`" error, or blocks on a rustup prompt.
+pub fn check_targets(conf: &Configuration) -> Result<()> {
+    if conf.builds_std() {
+        return Ok(());
+    }
+
+    let sysroot = sysroot()?;
+    let missing = missing_targets(&sysroot, &conf.cargo_section.chosen_targets());
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let names = missing.iter().map(Target::as_str).collect::<Vec<_>>();
+
+    if !conf.cli.install_targets {
+        bail!(
+            "The Rust standard library is not installed for {}. Install it with\n\n    rustup target add {}\n\nor run again with --install-targets.",
+            names.join(", "),
+            names.join(" ")
+        );
+    }
+
+    let subject = names.join(", ");
+    conf.emit(Event::StageStarted {
+        stage: Stage::InstallTargets,
+        subject: subject.clone(),
+    });
+    let mut args = vec!["target".to_string(), "add".to_string()];
+    args.extend(names.iter().map(|n| n.to_string()));
+    super::run("rustup", &args, &[], conf.observer.as_ref())
+        .context("installing targets with rustup")?;
+    conf.emit(Event::StageFinished {
+        stage: Stage::InstallTargets,
+        subject,
+        output: None,
+        checksum: None,
+    });
+    Ok(())
+}
+
+/// The sysroot of the toolchain cargo will use.
+pub fn sysroot() -> Result<Utf8PathBuf> {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let output = Command::new(&rustc)
+        .args(["--print", "sysroot"])
+        .output()
+        .with_context(|| format!("running {rustc} --print sysroot"))?;
+    if !output.status.success() {
+        bail!(
+            "{rustc} --print sysroot failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().into())
+}
+
+/// The targets with a standard library in the sysroot.
+pub fn installed_targets(sysroot: &Utf8Path) -> Vec<String> {
+    let rustlib = sysroot.join("lib").join("rustlib");
+    let mut targets = rustlib
+        .ls()
+        .dirs()
+        .filter(|dir| dir.join("lib").is_dir())
+        .filter_map(|dir| dir.file_name().map(str::to_string))
+        .collect::<Vec<_>>();
+    targets.sort();
+    targets
+}
+
+/// The targets without a prebuilt standard library in the sysroot.
+fn missing_targets(sysroot: &Utf8Path, targets: &[Target]) -> Vec<Target> {
+    let rustlib = sysroot.join("lib").join("rustlib");
+    targets
+        .iter()
+        .filter(|target| !rustlib.join(target.as_str()).join("lib").is_dir())
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_targets_are_found_in_the_sysroot() {
+        let dir = tempfile::tempdir().unwrap();
+        let sysroot = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        sysroot
+            .join("lib/rustlib/aarch64-apple-ios/lib")
+            .mkdirs()
+            .unwrap();
+
+        assert_eq!(installed_targets(&sysroot), ["aarch64-apple-ios"]);
+
+        let targets = [Target::IosDevice, Target::IosSimArm64, Target::MacosArm64];
+        assert_eq!(
+            missing_targets(&sysroot, &targets),
+            [Target::IosSimArm64, Target::MacosArm64]
+        );
+    }
+}
@@ -1,48 +1,211 @@
+use std::str::FromStr;
+
 use camino_fs::Utf8PathBuf;
 
-use super::LibType;
+use super::{LibTypes, Target};
 
 xflags::xflags! {
     src "./src/conf/args.rs"
 
     /// Compile a package into a cross-platform Apple XCFramework
     cmd xcframework{
-        /// Chose library type to build when Cargo.toml `crate-type` has both.
-        optional --lib-type lib_type: LibType
+        /// Build the XCFramework (the default when no subcommand is given)
+        default cmd build {
+            /// Chose library type to build when Cargo.toml `crate-type` has both,
+            /// staticlib, cdylib or both.
+            optional --lib-type lib_type: LibTypes
+
+            /// Do not print cargo log messages
+            optional --quiet
+
+            /// Package to build (see `cargo help pkgid`)
+            optional -p, --package package: String
+
+            /// Use verbose output (-v -v very verbose/build.rs output)
+            repeated -v, --verbose
+
+            /// Unstable (nightly-only) flags to Cargo, see 'cargo -Z help' for details
+            optional --unstable-flags unstable_flags: String
+
+            /// Build artifacts in release mode, with optimizations
+            optional -r, --release
+
+            /// Build artifacts with the specified profile
+            optional --profile profile: String
+
+            /// Space or comma separated list of features to activate
+            repeated -f, --features features: String
+
+            /// Activate all available features
+            optional --all-features
+
+            /// Do not activate the `default` feature
+            optional --no-default-features
+
+            /// Directory for all generated artifacts
+            optional --target-dir target_dir: Utf8PathBuf
+
+            /// Path to Cargo.toml.
+            optional --manifest-path manifest_path: Utf8PathBuf
+
+            /// Build twice and fail if the outputs differ
+            optional --verify-reproducible
+
+            /// Install the missing rustup targets instead of failing
+            optional --install-targets
+
+            /// Build for iOS, overriding Cargo.toml
+            optional --ios
+
+            /// Don't build for iOS, overriding Cargo.toml
+            optional --no-ios
+
+            /// Build for macOS, overriding Cargo.toml
+            optional --macos
+
+            /// Don't build for macOS, overriding Cargo.toml
+            optional --no-macos
+
+            /// Build the iOS simulator targets, overriding Cargo.toml
+            optional --simulators
+
+            /// Don't build the iOS simulator targets, overriding Cargo.toml
+            optional --no-simulators
+
+            /// Build only this target triple, can be repeated. Replaces the
+            /// platform and target selection of Cargo.toml
+            repeated --target target: Target
+
+            /// Create a zip archive of the XCFramework
+            optional --zip
+
+            /// Don't create a zip archive of the XCFramework
+            optional --no-zip
+
+            /// Directory with the module.modulemap and the headers, overriding Cargo.toml
+            optional --include-dir include_dir: Utf8PathBuf
+
+            /// Build the [package.metadata.xcframework.variants.<name>] variant
+            optional --variant variant: String
+
+            /// Print the build plan without building anything
+            optional --dry-run
+
+            /// Output format: table (default, text) or json. With json the
+            /// progress is printed as JSON lines, and --dry-run prints the plan as JSON.
+            optional --format format: OutputFormat
+
+            /// Coloring: auto (default), always or never. Auto honors NO_COLOR.
+            optional --color color: ColorChoice
+
+            /// Number of parallel jobs of cargo and of the platform slices,
+            /// defaults to the number of CPUs
+            optional -j, --jobs jobs: usize
+        }
+
+        /// Check an existing XCFramework for inconsistencies
+        cmd verify {
+            /// Path to the .xcframework directory
+            required path: Utf8PathBuf
+        }
+
+        /// Print the slices of an XCFramework or a zip archive of one
+        cmd inspect {
+            /// Path to the .xcframework directory or .zip archive
+            required path: Utf8PathBuf
+
+            /// Output format: table (default) or json
+            optional --format format: OutputFormat
+        }
+
+        /// Check that the tools, SDKs and targets needed for a build are available
+        cmd doctor {
+            /// Package to check (see `cargo help pkgid`)
+            optional -p, --package package: String
 
-        /// Do not print cargo log messages
-        optional --quiet
+            /// Path to Cargo.toml.
+            optional --manifest-path manifest_path: Utf8PathBuf
 
-        /// Package to build (see `cargo help pkgid`)
-        optional -p, --package package: String
+            /// Output format: table (default, a checklist) or json
+            optional --format format: OutputFormat
+        }
+    }
+}
 
-        /// Use verbose output (-v -v very verbose/build.rs output)
-        repeated -v, --verbose
+/// The output format of the informational subcommands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+}
 
-        /// Unstable (nightly-only) flags to Cargo, see 'cargo -Z help' for details
-        optional --unstable-flags unstable_flags: String
+impl FromStr for OutputFormat {
+    type Err = String;
 
-        /// Build artifacts in release mode, with optimizations
-        optional -r, --release
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
 
-        /// Build artifacts with the specified profile
-        optional --profile profile: String
+/// When to use colors in the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
 
-        /// Space or comma separated list of features to activate
-        repeated -f, --features features: String
+impl FromStr for ColorChoice {
+    type Err = String;
 
-        /// Activate all available features
-        optional --all-features
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("Unknown color choice: {}", s)),
+        }
+    }
+}
 
-        /// Do not activate the `default` feature
-        optional --no-default-features
+/// The arguments of a build, the default subcommand. Kept under the name
+/// the command line had before it got subcommands.
+pub type CliArgs = Build;
 
-        /// Directory for all generated artifacts
-        optional --target-dir target_dir: Utf8PathBuf
+impl Build {
+    /// Parse the build arguments from the command line, or print the help
+    /// or the error and exit.
+    pub fn from_env_or_exit() -> Self {
+        match Xcframework::from_env_or_exit().subcommand {
+            XcframeworkCmd::Build(build) => build,
+            _ => {
+                eprintln!("expected build arguments");
+                std::process::exit(2)
+            }
+        }
+    }
+
+    /// Parse the build arguments from the command line.
+    pub fn from_env() -> xflags::Result<Self> {
+        Self::from_cli(Xcframework::from_env()?)
+    }
 
-        /// Path to Cargo.toml.
-        optional --manifest-path manifest_path: Utf8PathBuf
+    /// Parse the build arguments.
+    pub fn from_vec(args: Vec<std::ffi::OsString>) -> xflags::Result<Self> {
+        Self::from_cli(Xcframework::from_vec(args)?)
+    }
 
+    fn from_cli(cli: Xcframework) -> xflags::Result<Self> {
+        match cli.subcommand {
+            XcframeworkCmd::Build(build) => Ok(build),
+            _ => Err(xflags::Error::new("expected build arguments")),
+        }
     }
 }
 
@@ -51,7 +214,20 @@ xflags::xflags! {
 // Run `env UPDATE_XFLAGS=1 cargo build` to regenerate.
 #[derive(Debug)]
 pub struct Xcframework {
-    pub lib_type: Option<LibType>,
+    pub subcommand: XcframeworkCmd,
+}
+
+#[derive(Debug)]
+pub enum XcframeworkCmd {
+    Build(Build),
+    Verify(Verify),
+    Inspect(Inspect),
+    Doctor(Doctor),
+}
+
+#[derive(Debug)]
+pub struct Build {
+    pub lib_type: Option<LibTypes>,
     pub quiet: bool,
     pub package: Option<String>,
     pub verbose: u32,
@@ -63,6 +239,42 @@ pub struct Xcframework {
     pub no_default_features: bool,
     pub target_dir: Option<Utf8PathBuf>,
     pub manifest_path: Option<Utf8PathBuf>,
+    pub verify_reproducible: bool,
+    pub install_targets: bool,
+    pub ios: bool,
+    pub no_ios: bool,
+    pub macos: bool,
+    pub no_macos: bool,
+    pub simulators: bool,
+    pub no_simulators: bool,
+    pub target: Vec<Target>,
+    pub zip: bool,
+    pub no_zip: bool,
+    pub include_dir: Option<Utf8PathBuf>,
+    pub variant: Option<String>,
+    pub dry_run: bool,
+    pub format: Option<OutputFormat>,
+    pub color: Option<ColorChoice>,
+    pub jobs: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct Verify {
+    pub path: Utf8PathBuf,
+}
+
+#[derive(Debug)]
+pub struct Inspect {
+    pub path: Utf8PathBuf,
+
+    pub format: Option<OutputFormat>,
+}
+
+#[derive(Debug)]
+pub struct Doctor {
+    pub package: Option<String>,
+    pub manifest_path: Option<Utf8PathBuf>,
+    pub format: Option<OutputFormat>,
 }
 
 impl Xcframework {
@@ -0,0 +1,341 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use camino_fs::Utf8PathBuf;
+
+use crate::Error;
+use crate::observer::BuildObserver;
+
+use super::{CliArgs, Configuration, LibType, LibTypes, PackageInfo, Target};
+
+/// Create a [`Configuration`] in Rust code, for example from a `cargo xtask`,
+/// instead of parsing command line arguments.
+///
+/// By default the package and its `[package.metadata.xcframework]` section
+/// are read with `cargo metadata`, like the command line does. Set both
+/// [`package_info`](Self::package_info) and [`section`](Self::section) to
+/// skip it.
+///
+/// ```no_run
+/// use xcframework::{ConfigurationBuilder, LibType};
+///
+/// let conf = ConfigurationBuilder::new()
+///     .manifest_path("mymath-lib/Cargo.toml")
+///     .lib_type(LibType::StaticLib)
+///     .targets(["aarch64-apple-ios".parse().unwrap()])
+///     .release()
+///     .build()?;
+/// xcframework::build(&conf)?;
+/// # Ok::<(), xcframework::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct ConfigurationBuilder {
+    cli: CliArgs,
+    package: Option<PackageInfo>,
+    section: Option<serde_json::Value>,
+    observer: Option<Arc<dyn BuildObserver>>,
+}
+
+impl Default for ConfigurationBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigurationBuilder {
+    pub fn new() -> Self {
+        let cli = CliArgs::from_vec(vec![]).expect("the build arguments are all optional");
+        Self {
+            cli,
+            package: None,
+            section: None,
+            observer: None,
+        }
+    }
+
+    /// The Cargo.toml to read the metadata from, `Cargo.toml` by default.
+    pub fn manifest_path(mut self, path: impl Into<Utf8PathBuf>) -> Self {
+        self.cli.manifest_path = Some(path.into());
+        self
+    }
+
+    /// The workspace package to build.
+    pub fn package(mut self, name: impl Into<String>) -> Self {
+        self.cli.package = Some(name.into());
+        self
+    }
+
+    /// Use these package details instead of reading them with `cargo metadata`.
+    /// Requires a [`section`](Self::section).
+    pub fn package_info(mut self, package: PackageInfo) -> Self {
+        self.package = Some(package);
+        self
+    }
+
+    /// Use this `[package.metadata.xcframework]` section instead of the one
+    /// in Cargo.toml.
+    pub fn section(mut self, section: serde_json::Value) -> Self {
+        self.section = Some(section);
+        self
+    }
+
+    pub fn lib_type(mut self, lib_type: LibType) -> Self {
+        self.cli.lib_type = Some(LibTypes::One(lib_type));
+        self
+    }
+
+    /// Build an XCFramework for each of the library types.
+    pub fn lib_types(mut self, lib_types: impl IntoIterator<Item = LibType>) -> Self {
+        self.cli.lib_type = Some(LibTypes::Many(lib_types.into_iter().collect()));
+        self
+    }
+
+    /// Build exactly these targets, replacing the platform selection.
+    pub fn targets(mut self, targets: impl IntoIterator<Item = Target>) -> Self {
+        self.cli.target = targets.into_iter().collect();
+        self
+    }
+
+    /// The directory with the module.modulemap and the headers.
+    pub fn include_dir(mut self, dir: impl Into<Utf8PathBuf>) -> Self {
+        self.cli.include_dir = Some(dir.into());
+        self
+    }
+
+    pub fn release(mut self) -> Self {
+        self.cli.release = true;
+        self
+    }
+
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.cli.profile = Some(profile.into());
+        self
+    }
+
+    pub fn features(mut self, features: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.cli.features = features.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn all_features(mut self) -> Self {
+        self.cli.all_features = true;
+        self
+    }
+
+    pub fn no_default_features(mut self) -> Self {
+        self.cli.no_default_features = true;
+        self
+    }
+
+    /// The directory for the cargo build and the XCFrameworks, the cargo
+    /// target directory by default.
+    pub fn output_dir(mut self, dir: impl Into<Utf8PathBuf>) -> Self {
+        self.cli.target_dir = Some(dir.into());
+        self
+    }
+
+    /// Build a `[package.metadata.xcframework.variants.<name>]` variant.
+    pub fn variant(mut self, name: impl Into<String>) -> Self {
+        self.cli.variant = Some(name.into());
+        self
+    }
+
+    /// The number of parallel jobs of cargo and of the platform slices.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.cli.jobs = Some(jobs);
+        self
+    }
+
+    /// Do not print cargo log messages.
+    pub fn quiet(mut self) -> Self {
+        self.cli.quiet = true;
+        self
+    }
+
+    /// Report the progress of the build to `observer` instead of the terminal.
+    pub fn observer(mut self, observer: impl BuildObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    pub fn build(mut self) -> Result<Configuration, Error> {
+        let observer = self.observer.take();
+        let mut conf = self.load().map_err(Error::Config)?;
+        if let Some(observer) = observer {
+            conf.observer = observer;
+        }
+        Ok(conf)
+    }
+
+    fn load(self) -> Result<Configuration> {
+        match (self.package, self.section) {
+            (Some(package), Some(section)) => {
+                Configuration::from_section(package, &section, self.cli)
+            }
+            (Some(_), None) => {
+                anyhow::bail!("A section is required when the package info is set")
+            }
+            (None, section) => {
+                let (package, metadata_section) = super::configuration::read_metadata(&self.cli)
+                    .context("reading the package metadata")?;
+                let section = section.unwrap_or(metadata_section);
+                Configuration::from_section(package, &section, self.cli)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    pub(crate) const EXAMPLE_DIR: &str = "examples/end-to-end/mymath-lib";
+
+    /// The end-to-end example package as a static library.
+    pub(crate) fn example_package() -> PackageInfo {
+        let dir = Utf8PathBuf::from(EXAMPLE_DIR);
+        PackageInfo {
+            name: "mymath-lib".into(),
+            version: "1.2.0".into(),
+            lib_name: "mymath".into(),
+            crate_types: vec![LibType::StaticLib],
+            manifest_dir: dir.clone(),
+            workspace_root: dir.clone(),
+            target_dir: dir.join("target"),
+        }
+    }
+
+    /// A builder for the example package with `section`, without reading
+    /// its metadata.
+    pub(crate) fn example(section: serde_json::Value) -> ConfigurationBuilder {
+        ConfigurationBuilder::new()
+            .package_info(example_package())
+            .section(section)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+
+    #[test]
+    fn without_metadata() {
+        let dir = Utf8PathBuf::from(EXAMPLE_DIR);
+        let conf = ConfigurationBuilder::new()
+            .package_info(PackageInfo {
+                crate_types: vec![LibType::StaticLib, LibType::CDyLib],
+                ..example_package()
+            })
+            .section(serde_json::json!({ "include-dir": "include", "macOS": true }))
+            .lib_types([LibType::CDyLib, LibType::StaticLib])
+            .targets(["aarch64-apple-ios-sim".parse().unwrap()])
+            .features(["simd"])
+            .release()
+            .output_dir("out")
+            .build()
+            .unwrap();
+
+        assert_eq!(conf.lib_types, [LibType::StaticLib, LibType::CDyLib]);
+        assert_eq!(conf.cargo_section.include_dir, dir.join("include"));
+        assert_eq!(conf.target_dir, "out");
+        assert_eq!(conf.profile(), "release");
+        assert_eq!(conf.cli.features, ["simd"]);
+        assert_eq!(conf.version, "1.2.0");
+        let targets = conf.cargo_section.chosen_targets();
+        assert_eq!(targets, ["aarch64-apple-ios-sim".parse().unwrap()]);
+    }
+
+    #[test]
+    fn build_std_for_static_libraries_or_when_passed() {
+        let section =
+            serde_json::json!({ "include-dir": "include", "iOS": true, "build-std": true });
+        let conf = example(section.clone()).build().unwrap();
+        assert!(conf.builds_std());
+        assert_eq!(conf.cli.unstable_flags.as_deref(), Some("build-std=std"));
+
+        let conf = ConfigurationBuilder::new()
+            .package_info(PackageInfo {
+                crate_types: vec![LibType::CDyLib],
+                ..example_package()
+            })
+            .section(section)
+            .build()
+            .unwrap();
+        assert!(!conf.builds_std());
+        assert_eq!(conf.cli.unstable_flags, None);
+
+        // Passed by hand, for example for a tier 3 target
+        let mut builder = example(serde_json::json!({ "include-dir": "include", "iOS": true }));
+        builder.cli.unstable_flags = Some("build-std=std,panic_abort".into());
+        assert!(builder.build().unwrap().builds_std());
+    }
+
+    #[test]
+    fn both_library_types_need_distinct_outputs() {
+        let both = |section: serde_json::Value| {
+            ConfigurationBuilder::new()
+                .package_info(PackageInfo {
+                    crate_types: vec![LibType::StaticLib, LibType::CDyLib],
+                    ..example_package()
+                })
+                .section(section)
+                .lib_types([LibType::StaticLib, LibType::CDyLib])
+                .build()
+                .map_err(|err| std::error::Error::source(&err).unwrap().to_string())
+        };
+
+        let err = both(serde_json::json!({
+            "include-dir": "include",
+            "iOS": true,
+            "output-name": "MyLib-{version}",
+        }))
+        .unwrap_err();
+        assert_eq!(
+            err,
+            "The output-name gives both library types the name MyLib-1.2.0, add the {module} placeholder"
+        );
+
+        let err = both(serde_json::json!({
+            "include-dir": "include",
+            "iOS": true,
+            "dynamic-module-name": "MyMath",
+        }))
+        .unwrap_err();
+        assert_eq!(
+            err,
+            "The dynamic-module-name must differ from the module name MyMath when both library types are built"
+        );
+
+        both(serde_json::json!({
+            "include-dir": "include",
+            "iOS": true,
+            "output-name": "{module}-{version}",
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn missing_crate_type() {
+        let err = example(serde_json::json!({ "include-dir": "include", "iOS": true }))
+            .lib_type(LibType::CDyLib)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            std::error::Error::source(&err).unwrap().to_string(),
+            "Please add 'cdylib' to '[lib] crate-type' in Cargo.toml"
+        );
+    }
+
+    #[test]
+    fn package_info_requires_a_section() {
+        let err = ConfigurationBuilder::new()
+            .package_info(example_package())
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            std::error::Error::source(&err).unwrap().to_string(),
+            "A section is required when the package info is set"
+        );
+    }
+}
@@ -1,27 +1,94 @@
+use std::sync::Arc;
+
 use crate::cmd::modulemap;
+use crate::core::plist::BundleInfo;
+use crate::core::{self, platform::ApplePlatform};
+use crate::observer::{self, BuildObserver};
 use anyhow::{Context, Result, anyhow, bail};
 use camino_fs::Utf8PathBuf;
 use cargo_metadata::{Metadata, MetadataCommand, Package, TargetKind};
 
-use super::{CliArgs, LibType, XCFrameworkConfiguration};
+use super::xcframework;
+use super::{CliArgs, LibType, LibTypes, XCFrameworkConfiguration};
 
 #[derive(Debug)]
 pub struct Configuration {
     pub cargo_section: XCFrameworkConfiguration,
     pub cli: CliArgs,
-    pub lib_type: LibType,
+    /// The library types to build, static first
+    pub lib_types: Vec<LibType>,
     // Name of the library (used for the compiled artifacts)
     pub lib_name: String,
+    /// Name of the package
+    pub package_name: String,
+    /// Version of the package
+    pub version: String,
+    /// The selected `[package.metadata.xcframework.variants.<name>]`
+    pub variant: Option<String>,
+    /// Root directory of the workspace
+    pub workspace_root: Utf8PathBuf,
     /// Directory for all generated artifacts
     pub target_dir: Utf8PathBuf,
     /// Directory where the xcframework will be built
     pub build_dir: Utf8PathBuf,
+    /// Receives the progress of the build
+    pub observer: Arc<dyn BuildObserver>,
+}
+
+/// The package details a [`Configuration`] needs, normally read with
+/// `cargo metadata`.
+#[derive(Debug, Clone)]
+pub struct PackageInfo {
+    /// Name of the package
+    pub name: String,
+    /// Version of the package
+    pub version: String,
+    /// Name of the `[lib]` target
+    pub lib_name: String,
+    /// The library types in the `[lib]` section's `crate-type`
+    pub crate_types: Vec<LibType>,
+    /// Directory of the package's Cargo.toml, relative paths in the
+    /// configuration resolve against it
+    pub manifest_dir: Utf8PathBuf,
+    /// Root directory of the workspace
+    pub workspace_root: Utf8PathBuf,
+    /// The cargo target directory
+    pub target_dir: Utf8PathBuf,
+}
+
+impl PackageInfo {
+    fn from_metadata(metadata: &Metadata, package: &Package) -> Self {
+        let lib = package.targets.iter().find(|t| {
+            t.kind.contains(&TargetKind::StaticLib) || t.kind.contains(&TargetKind::CDyLib)
+        });
+        let mut crate_types = vec![];
+        if let Some(lib) = lib {
+            if lib.kind.contains(&TargetKind::StaticLib) {
+                crate_types.push(LibType::StaticLib);
+            }
+            if lib.kind.contains(&TargetKind::CDyLib) {
+                crate_types.push(LibType::CDyLib);
+            }
+        }
+        Self {
+            name: package.name.to_string(),
+            version: package.version.to_string(),
+            lib_name: lib.map(|l| l.name.clone()).unwrap_or_default(),
+            crate_types,
+            manifest_dir: package
+                .manifest_path
+                .parent()
+                .map(Utf8PathBuf::from)
+                .unwrap_or_default(),
+            workspace_root: metadata.workspace_root.clone(),
+            target_dir: metadata.target_directory.clone(),
+        }
+    }
 }
 
 impl Configuration {
     pub fn new(
-        metadata: &Metadata,
-        package: &Package,
+        package: &PackageInfo,
         mut cli: CliArgs,
         xc_conf: XCFrameworkConfiguration,
     ) -> Result<Self> {
@@ -29,15 +96,22 @@ impl Configuration {
         let target_dir = cli
             .target_dir
             .as_ref()
-            .unwrap_or(&metadata.target_directory)
+            .unwrap_or(&package.target_dir)
             .clone();
 
         let build_dir = target_dir.join("xcframework");
-        let wanted_lib_type = cli.lib_type.clone().or_else(|| xc_conf.lib_type.clone());
+        let wanted_lib_types = cli
+            .lib_type
+            .as_ref()
+            .or(xc_conf.lib_type.as_ref())
+            .map(LibTypes::to_vec);
 
-        let (lib_type, target) = get_libtype(package, wanted_lib_type)?;
+        let lib_types = get_lib_types(&package.crate_types, wanted_lib_types)?;
+        if cli.jobs == Some(0) {
+            bail!("--jobs must be at least 1");
+        }
 
-        if xc_conf.build_std && lib_type == LibType::StaticLib {
+        if builds_std(&xc_conf, &lib_types) {
             let already_set = cli
                 .unstable_flags
                 .as_ref()
@@ -51,52 +125,165 @@ impl Configuration {
                 }
             }
         }
-        Ok(Self {
+        let variant = cli.variant.clone();
+        let observer = observer::from_cli(&cli);
+        let conf = Self {
             cargo_section: xc_conf,
             cli,
-            lib_type,
-            lib_name: target.name.clone(),
+            lib_types,
+            lib_name: package.lib_name.clone(),
+            package_name: package.name.clone(),
+            version: package.version.clone(),
+            variant,
+            workspace_root: package.workspace_root.clone(),
             target_dir,
             build_dir,
-        })
+            observer,
+        };
+        conf.check_distinct_outputs()?;
+        Ok(conf)
     }
 
-    pub fn load(cli: CliArgs) -> Result<Self> {
-        let manifest_path = cli
-            .manifest_path
-            .clone()
-            .unwrap_or_else(|| Utf8PathBuf::from("Cargo.toml"));
-        let mut dir = manifest_path.clone();
-        dir.pop();
-
-        let metadata = MetadataCommand::new().manifest_path(manifest_path).exec()?;
-
-        let workspace_packages = metadata.workspace_packages();
-        let package = if let Some(package) = &cli.package {
-            workspace_packages
-                .iter()
-                .find(|p| p.name.as_str() == package)
-                .ok_or(anyhow!("Could not find package '{package}'"))?
-        } else {
-            metadata
-                .root_package()
-                .ok_or(anyhow!("Could not find root package in metadata"))?
+    /// Building both library types must give two XCFrameworks that don't
+    /// overwrite each other. A missing modulemap is reported by the build.
+    fn check_distinct_outputs(&self) -> Result<()> {
+        let [static_lib, dynamic_lib] = self.lib_types.as_slice() else {
+            return Ok(());
         };
-
-        let Some(section) = package.metadata.get("xcframework") else {
-            bail!("Missing [package.metadata.xcframework] section in Cargo.toml");
+        let (Ok(static_module), Ok(dynamic_module)) = (
+            self.flavor_module_name(static_lib),
+            self.flavor_module_name(dynamic_lib),
+        ) else {
+            return Ok(());
         };
+        if static_module == dynamic_module {
+            bail!(
+                "The dynamic-module-name must differ from the module name {static_module} when both library types are built"
+            );
+        }
+        let static_name = self.output_name(static_lib)?;
+        if static_name == self.output_name(dynamic_lib)? {
+            bail!(
+                "The output-name gives both library types the name {static_name}, add the {{module}} placeholder"
+            );
+        }
+        Ok(())
+    }
 
-        let xc_conf = XCFrameworkConfiguration::parse(section, &dir, true)
+    pub fn load(cli: CliArgs) -> Result<Self> {
+        let (package, section) = read_metadata(&cli)?;
+        Self::from_section(package, &section, cli)
+    }
+
+    /// Create the configuration from a `[package.metadata.xcframework]`
+    /// section, without reading the package's metadata.
+    pub fn from_section(
+        package: PackageInfo,
+        section: &serde_json::Value,
+        cli: CliArgs,
+    ) -> Result<Self> {
+        let section = xcframework::select_variant(section, cli.variant.as_deref())?;
+
+        let mut xc_conf = XCFrameworkConfiguration::parse(&section, &package.manifest_dir, false)
+            .context("Error in Cargo.toml section [package.metadata.xcframework]")?;
+        xc_conf.apply_cli(&cli)?;
+        let xc_conf = xc_conf
+            .validated()
             .context("Error in Cargo.toml section [package.metadata.xcframework]")?;
 
-        Self::new(&metadata, package, cli, xc_conf)
+        Self::new(&package, cli, xc_conf)
     }
 
     pub fn module_name(&self) -> Result<String> {
         modulemap::get_module_name(self)
     }
 
+    /// The module name of the XCFramework built from a library type. When
+    /// both types are built, the dynamic one gets its own name so that the
+    /// two frameworks can be used side by side.
+    pub fn flavor_module_name(&self, lib_type: &LibType) -> Result<String> {
+        let module = self.module_name()?;
+        if self.lib_types.len() < 2 || *lib_type == LibType::StaticLib {
+            return Ok(module);
+        }
+        Ok(self
+            .cargo_section
+            .dynamic_module_name
+            .clone()
+            .unwrap_or_else(|| format!("{module}Dynamic")))
+    }
+
+    pub(crate) fn emit(&self, event: observer::Event) {
+        self.observer.event(&event);
+    }
+
+    /// The name of the XCFramework and its archives for a library type,
+    /// without extension
+    pub fn output_name(&self, lib_type: &LibType) -> Result<String> {
+        let module = self.flavor_module_name(lib_type)?;
+        let template = self
+            .cargo_section
+            .output_name
+            .as_deref()
+            .unwrap_or("{module}");
+        Ok(xcframework::format_output_name(
+            template,
+            &module,
+            self.variant.as_deref(),
+            &self.version,
+        ))
+    }
+
+    /// The identity, versions and custom keys written to the framework
+    /// Info.plist of a platform
+    pub fn bundle_info(&self, platform: &ApplePlatform) -> BundleInfo {
+        let build_number = std::env::var("XCFRAMEWORK_BUILD_NUMBER")
+            .ok()
+            .or_else(|| self.cargo_section.build_number.clone());
+        BundleInfo {
+            identifier: self.cargo_section.bundle_identifier.clone(),
+            custom_keys: self.cargo_section.info_plist_for(platform),
+            minimum_os_version: Some(self.deployment_target(platform)),
+            ..BundleInfo::from_package_version(&self.version, build_number)
+        }
+    }
+
+    /// Whether cargo compiles the standard library with `-Z build-std`
+    /// instead of using the installed one, from the `build-std` key or
+    /// `--unstable-flags`.
+    pub fn builds_std(&self) -> bool {
+        self.cli
+            .unstable_flags
+            .as_deref()
+            .is_some_and(|flags| flags.split(',').any(|f| f.trim().starts_with("build-std")))
+    }
+
+    /// The deployment target cargo builds the targets of a platform for,
+    /// from their `env` settings or the process environment. The newest one
+    /// wins when the targets differ.
+    pub fn deployment_target(&self, platform: &ApplePlatform) -> String {
+        self.cargo_section
+            .platform_targets()
+            .into_iter()
+            .filter(|(p, _)| p == platform)
+            .flat_map(|(_, targets)| targets.iter().copied())
+            .map(|target| {
+                let settings = self.cargo_section.cargo_settings_for(target);
+                core::resolve_deployment_target(platform, &settings.env)
+            })
+            .max_by(|a, b| core::macho::compare_versions(a, b))
+            .unwrap_or_else(|| core::resolve_deployment_target(platform, &Default::default()))
+    }
+
+    /// The number of platform slices processed at the same time.
+    pub fn jobs(&self) -> usize {
+        self.cli.jobs.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(usize::from)
+                .unwrap_or(1)
+        })
+    }
+
     pub fn profile(&self) -> &str {
         if self.cli.release {
             "release"
@@ -106,32 +293,78 @@ impl Configuration {
     }
 }
 
-fn get_libtype(
-    package: &Package,
-    libtype: Option<LibType>,
-) -> Result<(LibType, &cargo_metadata::Target)> {
-    let staticlib = package.targets.iter().find(|t| {
-        t.kind.contains(&TargetKind::StaticLib) || t.kind.contains(&TargetKind::StaticLib)
-    });
-    let dylib = package
-        .targets
-        .iter()
-        .find(|t| t.kind.contains(&TargetKind::CDyLib) || t.kind.contains(&TargetKind::CDyLib));
+/// `build-std` is only used for static libraries.
+fn builds_std(xc_conf: &XCFrameworkConfiguration, lib_types: &[LibType]) -> bool {
+    xc_conf.build_std && lib_types.contains(&LibType::StaticLib)
+}
+
+/// Read the package and its `[package.metadata.xcframework]` section, with
+/// the workspace defaults applied.
+pub(super) fn read_metadata(cli: &CliArgs) -> Result<(PackageInfo, serde_json::Value)> {
+    let manifest_path = cli
+        .manifest_path
+        .clone()
+        .unwrap_or_else(|| Utf8PathBuf::from("Cargo.toml"));
+
+    let metadata = MetadataCommand::new().manifest_path(manifest_path).exec()?;
+
+    let workspace_packages = metadata.workspace_packages();
+    let package = if let Some(package) = &cli.package {
+        workspace_packages
+            .iter()
+            .find(|p| p.name.as_str() == package)
+            .ok_or(anyhow!("Could not find package '{package}'"))?
+    } else {
+        metadata
+            .root_package()
+            .ok_or(anyhow!("Could not find root package in metadata"))?
+    };
+
+    let Some(section) = package.metadata.get("xcframework") else {
+        bail!("Missing [package.metadata.xcframework] section in Cargo.toml");
+    };
+
+    // Relative paths resolve against the manifest that declares them
+    let section = match metadata.workspace_metadata.get("xcframework") {
+        Some(defaults) => {
+            xcframework::inherit_workspace_defaults(defaults, section, &metadata.workspace_root)
+        }
+        None => section.clone(),
+    };
+    Ok((PackageInfo::from_metadata(&metadata, package), section))
+}
+
+fn get_lib_types(crate_types: &[LibType], lib_types: Option<Vec<LibType>>) -> Result<Vec<LibType>> {
+    let Some(lib_types) = lib_types else {
+        return Ok(vec![get_libtype(crate_types, None)?]);
+    };
+    if lib_types.is_empty() {
+        bail!("'[package.metadata.xcframework] lib-type' can't be empty");
+    }
+    for lib_type in &lib_types {
+        get_libtype(crate_types, Some(lib_type.clone()))?;
+    }
+    Ok(lib_types)
+}
+
+fn get_libtype(crate_types: &[LibType], libtype: Option<LibType>) -> Result<LibType> {
+    let staticlib = crate_types.contains(&LibType::StaticLib);
+    let dylib = crate_types.contains(&LibType::CDyLib);
     use LibType::*;
     Ok(match (staticlib, dylib, libtype) {
-        (Some(staticlib), None, None) => (StaticLib, staticlib),
-        (Some(staticlib), _, Some(StaticLib)) => (StaticLib, staticlib),
-        (Some(_staticlib), None, Some(CDyLib)) => {
+        (true, false, None) => StaticLib,
+        (true, _, Some(StaticLib)) => StaticLib,
+        (true, false, Some(CDyLib)) => {
             bail!("Please add 'cdylib' to '[lib] crate-type' in Cargo.toml")
         }
-        (None, Some(dylib), None) => (CDyLib, dylib),
-        (_, Some(dylib), Some(CDyLib)) => (CDyLib, dylib),
-        (_, Some(_dylib), Some(StaticLib)) => {
+        (false, true, None) => CDyLib,
+        (_, true, Some(CDyLib)) => CDyLib,
+        (_, true, Some(StaticLib)) => {
             bail!("Please add 'staticlib' to '[lib] crate-type' in Cargo.toml")
         }
-        (Some(_), Some(_), None) => {
+        (true, true, None) => {
             bail!("Please set '[package.metadata.xcframework] lib-type' in Cargo.toml")
         }
-        (None, None, _) => bail!("Missing '[lib] crate-type' in Cargo.toml"),
+        (false, false, _) => bail!("Missing '[lib] crate-type' in Cargo.toml"),
     })
 }
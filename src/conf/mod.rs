@@ -1,9 +1,18 @@
+// The build arguments are parsed once and not moved around
+#[allow(clippy::large_enum_variant)]
 mod args;
+mod builder;
 mod configuration;
 mod targets;
 mod xcframework;
 
-pub use crate::conf::xcframework::{LibType, XCFrameworkConfiguration};
-pub use args::Xcframework as CliArgs;
-pub use configuration::Configuration;
+pub use crate::conf::xcframework::{CargoSettings, LibType, LibTypes, XCFrameworkConfiguration};
+pub use args::{
+    CliArgs, ColorChoice, Doctor as DoctorArgs, Inspect as InspectArgs, OutputFormat,
+    Verify as VerifyArgs, Xcframework as Cli, XcframeworkCmd as Command,
+};
+pub use builder::ConfigurationBuilder;
+#[cfg(test)]
+pub(crate) use builder::testing;
+pub use configuration::{Configuration, PackageInfo};
 pub use targets::Target;
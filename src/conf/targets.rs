@@ -2,7 +2,9 @@ use std::{fmt::Display, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+use crate::core::platform::{ApplePlatform, Environment};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Target {
     IosDevice,
     IosSimArm64,
@@ -11,6 +13,15 @@ pub enum Target {
     MacosX86_64,
 }
 
+impl Serialize for Target {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 impl<'de> Deserialize<'de> for Target {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -55,6 +66,17 @@ impl FromStr for Target {
 }
 
 impl Target {
+    /// The platform the target builds for.
+    pub fn platform(&self) -> ApplePlatform {
+        match self {
+            Target::IosDevice => ApplePlatform::IOS(Environment::Device),
+            Target::IosSimArm64 | Target::IosSimX86_64 => {
+                ApplePlatform::IOS(Environment::Simulator)
+            }
+            Target::MacosArm64 | Target::MacosX86_64 => ApplePlatform::MacOS,
+        }
+    }
+
     pub fn default_macos() -> Vec<Target> {
         vec![Target::MacosX86_64, Target::MacosArm64]
     }
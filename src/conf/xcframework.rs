@@ -1,12 +1,19 @@
 #![allow(non_snake_case)]
 
-use super::Target;
-use anyhow::{Result, bail};
+use super::{CliArgs, Target};
+use crate::core::archive::ArchiveFormat;
+use crate::core::platform::{ApplePlatform, Environment};
+use crate::core::plist::check_custom_keys;
+use crate::core::privacy::PrivacyManifest;
+use crate::core::signing::SigningConfig;
+use anyhow::{Context, Result, bail};
 use camino_fs::*;
-use serde::Deserialize;
+use plist::Dictionary;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "lowercase")]
 pub enum LibType {
     StaticLib,
@@ -34,6 +41,41 @@ impl LibType {
     }
 }
 
+/// The library types to build, either `"staticlib"`, `"cdylib"` or a list of
+/// them. On the command line `both` selects the two.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum LibTypes {
+    One(LibType),
+    Many(Vec<LibType>),
+}
+
+impl FromStr for LibTypes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "both" => Ok(LibTypes::Many(vec![LibType::StaticLib, LibType::CDyLib])),
+            _ => s.parse().map(LibTypes::One),
+        }
+    }
+}
+
+impl LibTypes {
+    /// The selected library types, static first and without duplicates.
+    pub fn to_vec(&self) -> Vec<LibType> {
+        match self {
+            LibTypes::One(lib_type) => vec![lib_type.clone()],
+            LibTypes::Many(lib_types) => {
+                let mut lib_types = lib_types.clone();
+                lib_types.sort();
+                lib_types.dedup();
+                lib_types
+            }
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct XCFrameworkConfiguration {
@@ -43,14 +85,36 @@ pub struct XCFrameworkConfiguration {
     #[serde(default)]
     pub include_dir: Utf8PathBuf,
 
-    /// The library type (staticlib or cdylib)
+    /// The library type (staticlib or cdylib), or both of them
     /// only necessary if the package lib target defines both
-    pub lib_type: Option<LibType>,
+    pub lib_type: Option<LibTypes>,
+
+    /// The module name of the dynamic XCFramework when both library types
+    /// are built
+    pub dynamic_module_name: Option<String>,
 
-    /// Whether to zip the resulting XCFramework
+    /// Deprecated, same as `archive = ["zip"]`
     #[serde(default = "zip_default")]
     pub zip: bool,
 
+    /// The archive formats to produce from the XCFramework
+    #[serde(default)]
+    pub archive: Vec<ArchiveFormat>,
+
+    /// The compression level of the archives
+    pub compression_level: Option<u32>,
+
+    /// The CFBundleIdentifier of the frameworks
+    pub bundle_identifier: Option<String>,
+
+    /// The CFBundleVersion of the frameworks, overridden by the
+    /// `XCFRAMEWORK_BUILD_NUMBER` environment variable
+    pub build_number: Option<String>,
+
+    /// Additional keys for the framework Info.plist files
+    #[serde(default)]
+    pub info_plist: Dictionary,
+
     /// Enable Cargo to compile the standard library itself as part of a crate graph compilation.
     #[serde(default)]
     pub build_std: bool,
@@ -72,6 +136,276 @@ pub struct XCFrameworkConfiguration {
 
     #[serde(default = "Target::default_ios_sim")]
     pub iOS_simulator_targets: Vec<Target>,
+
+    /// Sign the frameworks and the XCFramework when set
+    pub signing: Option<SigningConfig>,
+
+    /// The privacy manifest to copy into every framework
+    pub privacy_manifest: Option<PrivacyManifest>,
+
+    /// Glob patterns for files to copy into the resources of every framework
+    #[serde(default)]
+    pub resources: Vec<String>,
+
+    /// Per-target cargo settings, merged over the platform ones
+    #[serde(default)]
+    pub target: BTreeMap<Target, CargoSettings>,
+
+    /// Per-platform overrides
+    #[serde(default)]
+    pub platform: PlatformOverrides,
+
+    /// The name of the XCFramework and its archives, a template with the
+    /// placeholders `{module}`, `{variant}` and `{version}`
+    pub output_name: Option<String>,
+}
+
+/// The `[package.metadata.xcframework.platform.<platform>]` sections.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PlatformOverrides {
+    pub iOS: Option<PlatformConfiguration>,
+    pub iOS_simulator: Option<PlatformConfiguration>,
+    pub macOS: Option<PlatformConfiguration>,
+}
+
+/// Settings that can be overridden for a single platform.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlatformConfiguration {
+    /// Replaces the top-level `resources` for this platform
+    pub resources: Option<Vec<String>>,
+
+    /// Merged over the top-level `info-plist` keys for this platform
+    pub info_plist: Option<Dictionary>,
+
+    #[serde(flatten)]
+    pub cargo: CargoSettings,
+}
+
+/// How cargo builds a platform or a target. Targets with different settings
+/// are built by separate cargo invocations.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CargoSettings {
+    /// Environment variables of the cargo invocation, like `CFLAGS` or
+    /// `SDKROOT`
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Flags passed to rustc in addition to the configured ones
+    #[serde(default)]
+    pub rustflags: Vec<String>,
+
+    /// Features enabled in addition to the ones of the command line
+    #[serde(default)]
+    pub features: Vec<String>,
+
+    /// Don't enable the default features
+    pub no_default_features: Option<bool>,
+}
+
+impl CargoSettings {
+    /// Merge `other` over these settings: its variables replace the ones
+    /// with the same name, its rustflags come last, its features are added
+    /// and its `no-default-features` wins when set.
+    fn merge(&mut self, other: &CargoSettings) {
+        self.env
+            .extend(other.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.rustflags.extend(other.rustflags.iter().cloned());
+        for feature in &other.features {
+            if !self.features.contains(feature) {
+                self.features.push(feature.clone());
+            }
+        }
+        self.no_default_features = other.no_default_features.or(self.no_default_features);
+    }
+
+    fn validate(&self) -> Result<()> {
+        for name in ["RUSTFLAGS", "CARGO_ENCODED_RUSTFLAGS"] {
+            if self.env.contains_key(name) {
+                bail!("Set 'rustflags' instead of {name} in 'env'");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PlatformOverrides {
+    pub fn get(&self, platform: &ApplePlatform) -> Option<&PlatformConfiguration> {
+        match platform {
+            ApplePlatform::IOS(Environment::Device) => self.iOS.as_ref(),
+            ApplePlatform::IOS(Environment::Simulator) => self.iOS_simulator.as_ref(),
+            ApplePlatform::MacOS => self.macOS.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &PlatformConfiguration> {
+        [&self.iOS, &self.iOS_simulator, &self.macOS]
+            .into_iter()
+            .flatten()
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut PlatformConfiguration> {
+        [&mut self.iOS, &mut self.iOS_simulator, &mut self.macOS]
+            .into_iter()
+            .flatten()
+    }
+}
+
+/// Merge a package section over the `[workspace.metadata.xcframework]`
+/// defaults, key by key. Tables are merged recursively, other values replace
+/// the defaults.
+///
+/// The relative paths of the defaults are resolved against the workspace
+/// root, so that they keep pointing to the same files from every package.
+pub fn inherit_workspace_defaults(
+    defaults: &serde_json::Value,
+    section: &serde_json::Value,
+    workspace_root: &Utf8Path,
+) -> serde_json::Value {
+    let mut merged = defaults.clone();
+    resolve_paths(&mut merged, workspace_root);
+    merge(&mut merged, section);
+    merged
+}
+
+/// Layer the `variants.<name>` table of a section over the rest of the section.
+///
+/// The `variants` table is removed, so that the result can be parsed as a
+/// [`XCFrameworkConfiguration`].
+pub fn select_variant(
+    section: &serde_json::Value,
+    variant: Option<&str>,
+) -> Result<serde_json::Value> {
+    let mut section = section.clone();
+    let variants = section
+        .as_object_mut()
+        .and_then(|section| section.remove("variants"))
+        .unwrap_or_default();
+    let Some(name) = variant else {
+        return Ok(section);
+    };
+    let Some(overrides) = variants.get(name) else {
+        let available = variants
+            .as_object()
+            .map(|v| v.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        if available.is_empty() {
+            bail!("Unknown variant '{name}', no variants are configured");
+        }
+        bail!(
+            "Unknown variant '{name}', the configured variants are: {}",
+            available.join(", ")
+        );
+    };
+    merge(&mut section, overrides);
+    Ok(section)
+}
+
+/// Tables that only make sense as a whole, so an override replaces them
+/// instead of being merged into them.
+const UNMERGED_TABLES: &[&str] = &["signing", "privacy-manifest"];
+
+/// Merge `overrides` into `base` key by key. Tables are merged recursively,
+/// except the [`UNMERGED_TABLES`], other values are replaced.
+fn merge(base: &mut serde_json::Value, overrides: &serde_json::Value) {
+    match (base, overrides) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(key) {
+                    Some(existing) if !UNMERGED_TABLES.contains(&key.as_str()) => {
+                        merge(existing, value)
+                    }
+                    _ => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides.clone(),
+    }
+}
+
+/// Expand the placeholders of an `output-name` template. The separator next
+/// to a placeholder that expands to nothing, like an unset variant, is
+/// dropped.
+pub fn format_output_name(
+    template: &str,
+    module: &str,
+    variant: Option<&str>,
+    version: &str,
+) -> String {
+    const SEPARATORS: [char; 3] = ['-', '_', '.'];
+    let placeholders = [
+        ("{module}", module),
+        ("{variant}", variant.unwrap_or_default()),
+        ("{version}", version),
+    ];
+
+    let mut name = String::new();
+    // Set when an empty placeholder starts the name, to drop the separator
+    // that follows it instead
+    let mut drop_separator = false;
+    let mut rest = template;
+    while let Some(c) = rest.chars().next() {
+        match placeholders.iter().find(|(p, _)| rest.starts_with(p)) {
+            Some((placeholder, value)) => {
+                if value.is_empty() {
+                    drop_separator = !name.ends_with(SEPARATORS);
+                    if !drop_separator {
+                        name.pop();
+                    }
+                } else {
+                    name.push_str(value);
+                    drop_separator = false;
+                }
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                if !(drop_separator && SEPARATORS.contains(&c)) {
+                    name.push(c);
+                }
+                drop_separator = false;
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    name
+}
+
+/// Make the path values of a section relative to `dir`.
+fn resolve_paths(section: &mut serde_json::Value, dir: &Utf8Path) {
+    use serde_json::Value;
+
+    let resolve = |value: &mut Value| {
+        if let Value::String(path) = value {
+            *path = dir.join(&*path).into_string();
+        }
+    };
+    let resolve_all = |value: Option<&mut Value>| {
+        if let Some(Value::Array(paths)) = value {
+            paths.iter_mut().for_each(resolve);
+        }
+    };
+
+    let Value::Object(section) = section else {
+        return;
+    };
+    if let Some(Value::Object(variants)) = section.get_mut("variants") {
+        for variant in variants.values_mut() {
+            resolve_paths(variant, dir);
+        }
+    }
+    section.get_mut("include-dir").map(resolve);
+    section.get_mut("privacy-manifest").map(resolve);
+    resolve_all(section.get_mut("resources"));
+    if let Some(Value::Object(platforms)) = section.get_mut("platform") {
+        for platform in platforms.values_mut() {
+            resolve_all(platform.get_mut("resources"));
+        }
+    }
 }
 
 pub fn zip_default() -> bool {
@@ -79,18 +413,147 @@ pub fn zip_default() -> bool {
 }
 
 impl XCFrameworkConfiguration {
-    pub fn chosen_targets(&self) -> Vec<Target> {
-        let mut all = vec![];
-        if self.macOS {
-            all.extend(self.macOS_targets.iter());
+    /// The archive formats, including zip when the deprecated `zip` flag is set
+    pub fn archive_formats(&self) -> Vec<ArchiveFormat> {
+        let mut formats = vec![];
+        if self.zip {
+            formats.push(ArchiveFormat::Zip);
+        }
+        for format in &self.archive {
+            if !formats.contains(format) {
+                formats.push(*format);
+            }
         }
+        formats
+    }
+
+    /// The platforms to build with their targets. Platforms without targets
+    /// are skipped.
+    pub fn platform_targets(&self) -> Vec<(ApplePlatform, &[Target])> {
+        let mut platforms = vec![];
         if self.iOS {
-            all.extend(self.iOS_targets.iter());
+            platforms.push((
+                ApplePlatform::IOS(Environment::Device),
+                self.iOS_targets.as_slice(),
+            ));
             if self.simulators {
-                all.extend(self.iOS_simulator_targets.iter());
+                platforms.push((
+                    ApplePlatform::IOS(Environment::Simulator),
+                    self.iOS_simulator_targets.as_slice(),
+                ));
             }
         }
-        all
+        if self.macOS {
+            platforms.push((ApplePlatform::MacOS, self.macOS_targets.as_slice()));
+        }
+        platforms.retain(|(_, targets)| !targets.is_empty());
+        platforms
+    }
+
+    pub fn chosen_targets(&self) -> Vec<Target> {
+        self.platform_targets()
+            .into_iter()
+            .flat_map(|(_, targets)| targets.iter().copied())
+            .collect()
+    }
+
+    /// Apply the command line overrides.
+    ///
+    /// `--target` replaces the platform and target selection and can't be
+    /// combined with the platform flags. The platform flags override the
+    /// corresponding Cargo.toml fields, and `--zip`/`--no-zip` add or remove
+    /// the zip archive.
+    pub fn apply_cli(&mut self, cli: &CliArgs) -> Result<()> {
+        let flag = |on: bool, off: bool, name: &str| -> Result<Option<bool>> {
+            match (on, off) {
+                (true, true) => bail!("--{name} and --no-{name} can't be combined"),
+                (true, false) => Ok(Some(true)),
+                (false, true) => Ok(Some(false)),
+                (false, false) => Ok(None),
+            }
+        };
+        let ios = flag(cli.ios, cli.no_ios, "ios")?;
+        let macos = flag(cli.macos, cli.no_macos, "macos")?;
+        let simulators = flag(cli.simulators, cli.no_simulators, "simulators")?;
+        let zip = flag(cli.zip, cli.no_zip, "zip")?;
+
+        if !cli.target.is_empty() {
+            if ios.is_some() || macos.is_some() || simulators.is_some() {
+                bail!("--target can't be combined with the --ios, --macos or --simulators flags");
+            }
+            let targets_for = |platform: ApplePlatform| {
+                cli.target
+                    .iter()
+                    .filter(|t| t.platform() == platform)
+                    .copied()
+                    .collect::<Vec<_>>()
+            };
+            self.iOS_targets = targets_for(ApplePlatform::IOS(Environment::Device));
+            self.iOS_simulator_targets = targets_for(ApplePlatform::IOS(Environment::Simulator));
+            self.macOS_targets = targets_for(ApplePlatform::MacOS);
+            self.simulators = !self.iOS_simulator_targets.is_empty();
+            self.iOS = !self.iOS_targets.is_empty() || self.simulators;
+            self.macOS = !self.macOS_targets.is_empty();
+        }
+        if let Some(ios) = ios {
+            self.iOS = ios;
+        }
+        if let Some(macos) = macos {
+            self.macOS = macos;
+        }
+        if let Some(simulators) = simulators {
+            self.simulators = simulators;
+        }
+
+        match zip {
+            Some(true) => self.zip = true,
+            Some(false) => {
+                self.zip = false;
+                self.archive.retain(|f| *f != ArchiveFormat::Zip);
+            }
+            None => {}
+        }
+
+        if let Some(include_dir) = &cli.include_dir {
+            self.include_dir = include_dir.clone();
+        }
+        Ok(())
+    }
+
+    /// The resource glob patterns for a platform
+    pub fn resources_for(&self, platform: &ApplePlatform) -> &[String] {
+        self.platform
+            .get(platform)
+            .and_then(|p| p.resources.as_deref())
+            .unwrap_or(&self.resources)
+    }
+
+    /// The cargo settings of a target, the ones of its platform with the
+    /// `[package.metadata.xcframework.target.<triple>]` section merged over
+    pub fn cargo_settings_for(&self, target: Target) -> CargoSettings {
+        let mut settings = CargoSettings::default();
+        if let Some(platform) = self.platform.get(&target.platform()) {
+            settings.merge(&platform.cargo);
+        }
+        if let Some(overrides) = self.target.get(&target) {
+            settings.merge(overrides);
+        }
+        settings
+    }
+
+    /// The custom Info.plist keys for a platform
+    pub fn info_plist_for(&self, platform: &ApplePlatform) -> Dictionary {
+        let mut keys = self.info_plist.clone();
+        if let Some(overrides) = self
+            .platform
+            .get(platform)
+            .and_then(|p| p.info_plist.as_ref())
+        {
+            for (key, value) in overrides {
+                keys.insert(key.clone(), value.clone());
+            }
+        }
+        keys
     }
 
     /// Parses the [package.metadata.xcframework] section of the Cargo.toml
@@ -102,10 +565,22 @@ impl XCFrameworkConfiguration {
     ) -> Result<Self> {
         let mut me = serde_json::from_value::<Self>(section.clone())?;
         me.include_dir = package_dir.join(me.include_dir);
+        me.privacy_manifest = me.privacy_manifest.map(|m| m.relative_to(package_dir));
+        let resolve = |patterns: &mut Vec<String>| {
+            for pattern in patterns {
+                *pattern = package_dir.join(&*pattern).into_string();
+            }
+        };
+        resolve(&mut me.resources);
+        for platform in me.platform.iter_mut() {
+            if let Some(resources) = &mut platform.resources {
+                resolve(resources);
+            }
+        }
         if validate { me.validated() } else { Ok(me) }
     }
 
-    fn validated(self) -> Result<Self> {
+    pub fn validated(self) -> Result<Self> {
         if self.include_dir.as_str().is_empty() {
             bail!("The include-dir field is required");
         }
@@ -114,9 +589,389 @@ impl XCFrameworkConfiguration {
             bail!("The include-dir '{}' does not exist", self.include_dir);
         }
 
-        if !self.iOS && !self.macOS {
+        if self.platform_targets().is_empty() {
             bail!("Nothing to build. At least one the fields 'iOS' or 'macOS' must be set to true");
         }
+
+        if let Some(signing) = &self.signing {
+            signing.validate()?;
+        }
+
+        // The level applies to every archive, so it must suit all formats
+        let formats = self.archive_formats();
+        let max_level = formats
+            .iter()
+            .map(ArchiveFormat::max_compression_level)
+            .min()
+            .unwrap_or(9);
+        if let Some(level) = self.compression_level
+            && level > max_level
+        {
+            let formats = formats.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+            bail!(
+                "The compression-level must be between 0 and {max_level} for the archive formats {}, not {level}",
+                formats.join(", ")
+            );
+        }
+
+        if let Some(template) = &self.output_name {
+            let mut rest = template.as_str();
+            while let Some(start) = rest.find('{') {
+                let end = rest[start..]
+                    .find('}')
+                    .with_context(|| format!("Unclosed placeholder in output-name '{template}'"))?;
+                let placeholder = &rest[start..start + end + 1];
+                if !["{module}", "{variant}", "{version}"].contains(&placeholder) {
+                    bail!("Unknown placeholder {placeholder} in output-name '{template}'");
+                }
+                rest = &rest[start + end + 1..];
+            }
+            if template.contains(['/', '\\']) {
+                bail!("The output-name '{template}' must not contain path separators");
+            }
+            // The module name and version are never empty, the variant can be
+            if format_output_name(template, "module", None, "version").is_empty() {
+                bail!("The output-name '{template}' is empty when no variant is built");
+            }
+        }
+
+        // The glob crate matches braces literally instead of expanding them
+        let platform_resources = self.platform.iter().filter_map(|p| p.resources.as_ref());
+        for pattern in self.resources.iter().chain(platform_resources.flatten()) {
+            if pattern.contains('{') && pattern.contains('}') {
+                bail!(
+                    "Brace expansion is not supported in the resources pattern '{pattern}', list the patterns separately"
+                );
+            }
+        }
+
+        check_custom_keys(&self.info_plist).context("in info-plist")?;
+        for platform in [
+            &self.platform.iOS,
+            &self.platform.iOS_simulator,
+            &self.platform.macOS,
+        ] {
+            if let Some(keys) = platform.as_ref().and_then(|p| p.info_plist.as_ref()) {
+                check_custom_keys(keys).context("in platform info-plist")?;
+            }
+            if let Some(platform) = platform {
+                platform.cargo.validate().context("in platform settings")?;
+            }
+        }
+        for (target, settings) in &self.target {
+            settings
+                .validate()
+                .with_context(|| format!("in target.{target}"))?;
+        }
+
+        if let Some(manifest) = &self.privacy_manifest {
+            manifest.load()?;
+        }
         Ok(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configuration(section: serde_json::Value) -> XCFrameworkConfiguration {
+        XCFrameworkConfiguration::parse(&section, Utf8Path::new("."), false).unwrap()
+    }
+
+    fn cli(args: &[&str]) -> CliArgs {
+        CliArgs::from_vec(args.iter().map(|s| s.into()).collect()).unwrap()
+    }
+
+    #[test]
+    fn resources_reject_brace_expansion() {
+        let err = configuration(serde_json::json!({
+            "include-dir": ".",
+            "iOS": true,
+            "platform": { "iOS": { "resources": ["assets/{a,b}.png"] } },
+        }))
+        .validated()
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("Brace expansion is not supported"), "{err}");
+    }
+
+    #[test]
+    fn compression_level_suits_every_archive_format() {
+        let section = |archive: serde_json::Value, level: u32| {
+            configuration(serde_json::json!({
+                "include-dir": ".",
+                "iOS": true,
+                "archive": archive,
+                "compression-level": level,
+            }))
+        };
+        section(serde_json::json!(["tar.zst"]), 19)
+            .validated()
+            .unwrap();
+
+        let err = section(serde_json::json!(["zip", "tar.zst"]), 19)
+            .validated()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The compression-level must be between 0 and 9 for the archive formats zip, tar.zst, not 19"
+        );
+        section(serde_json::json!(["zip", "tar.zst"]), 9)
+            .validated()
+            .unwrap();
+    }
+
+    #[test]
+    fn target_settings_merge_over_the_platform() {
+        let conf = configuration(serde_json::json!({
+            "include-dir": "include",
+            "iOS": true,
+            "simulators": true,
+            "platform": {
+                "iOS-simulator": {
+                    "env": { "CFLAGS": "-O2", "SDKROOT": "iphonesimulator" },
+                    "rustflags": ["-Ctarget-cpu=apple-a14"],
+                },
+            },
+            "target": {
+                "x86_64-apple-ios": {
+                    "env": { "CFLAGS": "-O1" },
+                    "rustflags": ["-Ctarget-feature=+sse4.2"],
+                },
+            },
+        }));
+
+        let settings = conf.cargo_settings_for(Target::IosSimX86_64);
+        assert_eq!(settings.env["CFLAGS"], "-O1");
+        assert_eq!(settings.env["SDKROOT"], "iphonesimulator");
+        assert_eq!(
+            settings.rustflags,
+            ["-Ctarget-cpu=apple-a14", "-Ctarget-feature=+sse4.2"]
+        );
+        assert_eq!(
+            conf.cargo_settings_for(Target::IosDevice),
+            CargoSettings::default()
+        );
+
+        let err = serde_json::from_value::<XCFrameworkConfiguration>(serde_json::json!({
+            "include-dir": "include",
+            "target": { "aarch64-apple-ios": { "rustflag": [] } },
+        }))
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("unknown field `rustflag`"),
+            "{err}"
+        );
+
+        let conf = configuration(serde_json::json!({
+            "include-dir": ".",
+            "iOS": true,
+            "target": { "aarch64-apple-ios": { "env": { "RUSTFLAGS": "-g" } } },
+        }));
+        let err = conf.validated().unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "in target.aarch64-apple-ios: Set 'rustflags' instead of RUSTFLAGS in 'env'"
+        );
+    }
+
+    #[test]
+    fn packages_inherit_workspace_defaults() {
+        let defaults = serde_json::json!({
+            "include-dir": "include",
+            "iOS": true,
+            "resources": ["assets/*.pem"],
+            "info-plist": { "NSHumanReadableCopyright": "Acme", "Team": "core" },
+        });
+        let section = serde_json::json!({
+            "iOS": false,
+            "macOS": true,
+            "info-plist": { "Team": "math" },
+        });
+
+        let merged = inherit_workspace_defaults(&defaults, &section, Utf8Path::new("/workspace"));
+        assert_eq!(
+            merged,
+            serde_json::json!({
+                "include-dir": "/workspace/include",
+                "iOS": false,
+                "macOS": true,
+                "resources": ["/workspace/assets/*.pem"],
+                "info-plist": { "NSHumanReadableCopyright": "Acme", "Team": "math" },
+            })
+        );
+
+        // The package's own paths stay relative to the package
+        let section = serde_json::json!({ "include-dir": "headers" });
+        let merged = inherit_workspace_defaults(&defaults, &section, Utf8Path::new("/workspace"));
+        let conf =
+            XCFrameworkConfiguration::parse(&merged, Utf8Path::new("/workspace/mymath"), false)
+                .unwrap();
+        assert_eq!(conf.include_dir, "/workspace/mymath/headers");
+        assert_eq!(conf.resources, ["/workspace/assets/*.pem"]);
+    }
+
+    #[test]
+    fn signing_and_privacy_manifest_are_replaced_as_a_whole() {
+        let defaults = serde_json::json!({
+            "signing": { "command": "scripts/sign.sh" },
+            "privacy-manifest": { "NSPrivacyTracking": false, "NSPrivacyTrackingDomains": [] },
+        });
+        let section = serde_json::json!({
+            "signing": { "identity": "Developer ID Application: Acme" },
+            "privacy-manifest": { "NSPrivacyTracking": true },
+        });
+
+        let merged = inherit_workspace_defaults(&defaults, &section, Utf8Path::new("/workspace"));
+        assert_eq!(merged, section);
+
+        let section = serde_json::json!({
+            "signing": { "command": "scripts/sign.sh" },
+            "variants": { "store": { "signing": { "identity": "Apple Distribution" } } },
+        });
+        let store = select_variant(&section, Some("store")).unwrap();
+        assert_eq!(
+            store,
+            serde_json::json!({ "signing": { "identity": "Apple Distribution" } })
+        );
+    }
+
+    #[test]
+    fn lib_types() {
+        let both: LibTypes = "both".parse().unwrap();
+        assert_eq!(both.to_vec(), [LibType::StaticLib, LibType::CDyLib]);
+        let one: LibTypes = "cdylib".parse().unwrap();
+        assert_eq!(one.to_vec(), [LibType::CDyLib]);
+        assert!("dylib".parse::<LibTypes>().is_err());
+
+        let conf = configuration(serde_json::json!({
+            "include-dir": ".",
+            "lib-type": ["cdylib", "staticlib", "cdylib"],
+        }));
+        let lib_types = conf.lib_type.unwrap().to_vec();
+        assert_eq!(lib_types, [LibType::StaticLib, LibType::CDyLib]);
+    }
+
+    #[test]
+    fn variants_are_layered_over_the_section() {
+        let section = serde_json::json!({
+            "iOS": true,
+            "macOS": true,
+            "zip": true,
+            "variants": {
+                "dev": { "macOS": false, "zip": false, "iOS-targets": [] },
+                "release": { "archive": ["zip", "tar.gz"] },
+            },
+        });
+
+        let dev = select_variant(&section, Some("dev")).unwrap();
+        assert_eq!(
+            dev,
+            serde_json::json!({ "iOS": true, "macOS": false, "zip": false, "iOS-targets": [] })
+        );
+        let base = select_variant(&section, None).unwrap();
+        assert_eq!(
+            base,
+            serde_json::json!({ "iOS": true, "macOS": true, "zip": true })
+        );
+
+        let err = select_variant(&section, Some("beta")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown variant 'beta', the configured variants are: dev, release"
+        );
+    }
+
+    #[test]
+    fn output_name_placeholders() {
+        let template = "{module}-{variant}";
+        assert_eq!(
+            format_output_name(template, "MyMath", Some("dev"), "1.0.0"),
+            "MyMath-dev"
+        );
+        assert_eq!(
+            format_output_name(template, "MyMath", None, "1.0.0"),
+            "MyMath"
+        );
+        assert_eq!(
+            format_output_name("{module}_{version}", "MyMath", None, "1.0.0"),
+            "MyMath_1.0.0"
+        );
+        let template = "{module}-{variant}-{version}";
+        assert_eq!(
+            format_output_name(template, "MyMath", None, "1.0.0"),
+            "MyMath-1.0.0"
+        );
+        assert_eq!(
+            format_output_name(template, "MyMath", Some("dev"), "1.0.0"),
+            "MyMath-dev-1.0.0"
+        );
+        assert_eq!(
+            format_output_name("{variant}.{module}", "MyMath", None, "1.0.0"),
+            "MyMath"
+        );
+
+        let validate = |template: &str| {
+            configuration(serde_json::json!({
+                "include-dir": ".",
+                "iOS": true,
+                "output-name": template,
+            }))
+            .validated()
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            validate("{variant}"),
+            Err("The output-name '{variant}' is empty when no variant is built".into())
+        );
+        assert_eq!(
+            validate("dist/{module}"),
+            Err("The output-name 'dist/{module}' must not contain path separators".into())
+        );
+        assert_eq!(validate("Acme{variant}"), Ok(()));
+
+        let conf = configuration(serde_json::json!({
+            "include-dir": ".",
+            "iOS": true,
+            "output-name": "{module}-{flavor}",
+        }));
+        let err = conf.validated().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown placeholder {flavor} in output-name '{module}-{flavor}'"
+        );
+    }
+
+    #[test]
+    fn cli_flags_override_the_platforms() {
+        let mut conf = configuration(serde_json::json!({ "iOS": true, "zip": true }));
+        conf.apply_cli(&cli(&["--no-ios", "--macos", "--no-zip"]))
+            .unwrap();
+        assert_eq!(conf.chosen_targets(), Target::default_macos());
+        assert!(conf.archive_formats().is_empty());
+
+        let err = conf.apply_cli(&cli(&["--ios", "--no-ios"])).unwrap_err();
+        assert_eq!(err.to_string(), "--ios and --no-ios can't be combined");
+    }
+
+    #[test]
+    fn cli_targets_replace_the_selection() {
+        let mut conf = configuration(serde_json::json!({ "iOS": true, "macOS": true }));
+        conf.apply_cli(&cli(&["--target", "aarch64-apple-ios-sim"]))
+            .unwrap();
+        assert_eq!(
+            conf.platform_targets(),
+            [(
+                ApplePlatform::IOS(Environment::Simulator),
+                [Target::IosSimArm64].as_slice()
+            )]
+        );
+
+        let err = conf
+            .apply_cli(&cli(&["--target", "aarch64-apple-ios", "--macos"]))
+            .unwrap_err();
+        assert!(err.to_string().contains("can't be combined"), "{err}");
+    }
+}
@@ -1,12 +1,19 @@
-use std::collections::HashMap;
-
 use anyhow::{Context, Ok};
 use camino_fs::*;
 use platform::ApplePlatform;
+use std::collections::BTreeMap;
 use xshell::{Shell, cmd};
 
+pub mod archive;
+pub mod inspect;
+pub mod macho;
+pub mod modulemap;
 pub mod platform;
 pub mod plist;
+pub mod privacy;
+pub mod reproducible;
+pub mod signing;
+pub mod verify;
 
 /// The frameworks can be static or dynamic.
 /// From rust perspective, it's crate type: cdylib or staticlib.
@@ -16,41 +23,103 @@ pub enum CrateType {
     Staticlib,
 }
 
-/// Create a universal library for each platform using lipo.
-pub fn lipo_create_platform_libraries(
-    platform_lib_paths: &HashMap<ApplePlatform, Vec<Utf8PathBuf>>,
-    output_lib_name: &str,
-    output_dir: &Utf8PathBuf,
-) -> anyhow::Result<HashMap<ApplePlatform, Utf8PathBuf>> {
-    let sh = Shell::new()?;
-    output_dir.mkdirs()?;
-
-    let mut libs = HashMap::new();
-    for (platform, paths) in platform_lib_paths.iter() {
-        if paths.len() == 1 {
-            // No need to lipo
-            libs.insert(platform.clone(), paths[0].clone());
-            continue;
-        }
-        let platform_dir = output_dir.join(format!("{:?}", platform));
-        platform_dir.mkdirs()?;
-        let output_path = platform_dir.join(output_lib_name);
+/// A file to copy into the resources of each framework.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleResource {
+    pub source: Utf8PathBuf,
+    /// The destination, relative to the framework's resources directory.
+    pub destination: Utf8PathBuf,
+}
 
-        let mut cmd = cmd!(sh, "lipo -create");
-        for path in paths {
-            cmd = cmd.arg(path);
+impl BundleResource {
+    /// Expand a glob pattern into the resources it matches.
+    ///
+    /// The destination keeps the path relative to the part of the pattern
+    /// before the first wildcard, so `assets/**/*.pem` copies
+    /// `assets/certs/ca.pem` to `certs/ca.pem`. Matched directories are
+    /// copied recursively.
+    pub fn from_glob(pattern: &str) -> anyhow::Result<Vec<Self>> {
+        let base = glob_base(pattern);
+
+        let mut resources = vec![];
+        for entry in glob::glob(pattern)? {
+            let path = Utf8PathBuf::from_path_buf(entry?)
+                .map_err(|p| anyhow::anyhow!("non UTF-8 path: {}", p.display()))?;
+            let files = if path.is_dir() {
+                path.ls().recurse().files().collect()
+            } else {
+                vec![path]
+            };
+            for source in files {
+                let destination = source
+                    .strip_prefix(&base)
+                    .with_context(|| format!("resource {source} is outside of {base}"))?
+                    .to_path_buf();
+                resources.push(Self {
+                    source,
+                    destination,
+                });
+            }
         }
-        cmd = cmd.arg("-output").arg(&output_path);
-        println!("🍭 Running lipo create for platform: {platform:?} ...");
-        cmd.run()?;
-        println!("✅ Run lipo create success, platform: {platform:?}, output:\n{output_path}");
-        libs.insert(platform.clone(), output_path);
+        if resources.is_empty() {
+            anyhow::bail!("No resources found matching '{pattern}'");
+        }
+        resources.sort_by(|a, b| a.destination.cmp(&b.destination));
+        Ok(resources)
+    }
+}
+
+/// The directory that paths matched by a glob pattern are relative to.
+fn glob_base(pattern: &str) -> Utf8PathBuf {
+    let path = Utf8Path::new(pattern);
+    let literal = path
+        .components()
+        .take_while(|c| !c.as_str().contains(['*', '?', '[']))
+        .collect::<Utf8PathBuf>();
+    if literal == path {
+        // Without wildcards the pattern names a single file or directory
+        literal
+            .parent()
+            .map(Utf8Path::to_path_buf)
+            .unwrap_or_default()
+    } else {
+        literal
+    }
+}
+
+/// The files besides the binary that go into a framework.
+#[derive(Debug, Clone, Default)]
+pub struct FrameworkContents {
+    pub header_paths: Vec<Utf8PathBuf>,
+    pub module_path: Utf8PathBuf,
+    pub resources: Vec<BundleResource>,
+}
+
+/// Create the universal library of a platform from the libraries of its
+/// targets using lipo. A single library is used as is.
+pub fn lipo_create_library(paths: &[Utf8PathBuf], output_path: &Utf8Path) -> anyhow::Result<()> {
+    if let [path] = paths
+        && path == output_path
+    {
+        // No need to lipo
+        return Ok(());
     }
-    Ok(libs)
+    let sh = Shell::new()?;
+    if let Some(dir) = output_path.parent() {
+        dir.mkdirs()?;
+    }
+
+    let mut cmd = cmd!(sh, "lipo -create");
+    for path in paths {
+        cmd = cmd.arg(path);
+    }
+    cmd = cmd.arg("-output").arg(output_path);
+    cmd.quiet().run()?;
+    Ok(())
 }
 
 /// Query the SDK version for a platform using `xcrun --show-sdk-version`.
-fn query_sdk_version(sh: &Shell, platform: &ApplePlatform) -> String {
+pub(crate) fn query_sdk_version(sh: &Shell, platform: &ApplePlatform) -> String {
     let sdk = platform.platform_name();
     sh.cmd("xcrun")
         .args(["--show-sdk-version", "--sdk", sdk])
@@ -60,11 +129,53 @@ fn query_sdk_version(sh: &Shell, platform: &ApplePlatform) -> String {
         .to_string()
 }
 
-/// Resolve the deployment target for a platform by checking the corresponding
-/// environment variable, falling back to the platform's default.
-fn resolve_deployment_target(platform: &ApplePlatform) -> String {
-    std::env::var(platform.deployment_target_env_var())
-        .unwrap_or_else(|_| platform.default_deployment_target().to_string())
+/// Resolve the deployment target for a platform from the environment of its
+/// cargo build, then the process environment, falling back to the
+/// platform's default.
+pub fn resolve_deployment_target(
+    platform: &ApplePlatform,
+    env: &BTreeMap<String, String>,
+) -> String {
+    let var = platform.deployment_target_env_var();
+    env.get(var)
+        .cloned()
+        .or_else(|| std::env::var(var).ok())
+        .unwrap_or_else(|| platform.default_deployment_target().to_string())
+}
+
+/// Check that every object in the library, each member of a static archive or
+/// the dylib, was built for `platform` and for at most its deployment target.
+///
+/// Objects built for another platform, typically C dependencies compiled by
+/// a misconfigured build script, otherwise only show up as Xcode link errors.
+pub fn validate_library(
+    platform: &ApplePlatform,
+    lib_path: &Utf8Path,
+    deployment_target: &str,
+) -> anyhow::Result<()> {
+    let objects = macho::read_file(lib_path)?;
+
+    let problems = objects
+        .iter()
+        .filter_map(|object| {
+            // rustc raises the deployment target of newer architectures
+            let minimum = platform.minimum_deployment_target(&object.arch);
+            let target = if macho::compare_versions(minimum, deployment_target).is_gt() {
+                minimum
+            } else {
+                deployment_target
+            };
+            macho::check_object(object, platform, Some(target))
+        })
+        .collect::<Vec<_>>();
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "{lib_path} doesn't match the {} slice:\n  - {}",
+            platform.platform_display_name(),
+            problems.join("\n  - ")
+        );
+    }
+    Ok(())
 }
 
 /// Reference: [article](https://developer.apple.com/documentation/xcode/creating-a-multi-platform-binary-framework-bundle#Determine-the-architectures-a-binary-supports)
@@ -76,70 +187,124 @@ pub fn wrap_as_framework(
     platform: ApplePlatform,
     crate_type: &CrateType,
     lib_path: &Utf8PathBuf,
-    header_paths: Vec<Utf8PathBuf>,
-    module_path: Utf8PathBuf,
+    contents: &FrameworkContents,
+    info: &plist::BundleInfo,
     bundle_name: &str,
     output_dir: &Utf8PathBuf,
 ) -> anyhow::Result<Utf8PathBuf> {
-    const SUFFIX: &str = ".framework";
-
-    println!("📦 Wrapping {:?} libraries as framework ...", platform);
-
     let sh = Shell::new()?;
 
-    let output_path = output_dir
-        .join(format!("{:?}", platform))
-        .join(format!("{}{}", bundle_name, SUFFIX));
-    output_path.mkdirs()?;
+    let output_path = framework_path(&platform, bundle_name, output_dir);
+    output_path.rm()?;
+
+    // macOS frameworks use the deep bundle layout where the content lives in
+    // Versions/A and the top level only has symlinks into Versions/Current.
+    let versioned = platform.has_versioned_bundle();
+    let content_dir = if versioned {
+        output_path.join("Versions").join("A")
+    } else {
+        output_path.clone()
+    };
+    let resources_dir = if versioned {
+        content_dir.join("Resources")
+    } else {
+        content_dir.clone()
+    };
+    resources_dir.mkdirs()?;
 
     let sdk_version = query_sdk_version(&sh, &platform);
-    let min_os_version = resolve_deployment_target(&platform);
-
-    let plist = plist::InfoPlistBuilder::new(bundle_name, platform, sdk_version, min_os_version);
-    let plist_path = output_path.join("Info.plist");
-    plist.write(plist_path.as_str())?;
-
-    sh.cmd("plutil")
-        .args(&[
-            "-convert",
-            "binary1",
-            "-o",
-            &format!("{}/Info.plist", output_path),
-            plist_path.as_str(),
-        ])
-        .run()?;
+    let min_os_version = info
+        .minimum_os_version
+        .clone()
+        .unwrap_or_else(|| resolve_deployment_target(&platform, &BTreeMap::new()));
+
+    let plist =
+        plist::InfoPlistBuilder::new(bundle_name, platform.clone(), sdk_version, min_os_version)
+            .bundle_info(info.clone());
+    let plist_path = resources_dir.join("Info.plist");
+    let write_plist = || -> anyhow::Result<()> {
+        plist.write(plist_path.as_str())?;
+        sh.cmd("plutil")
+            .args(&[
+                "-convert",
+                "binary1",
+                "-o",
+                plist_path.as_str(),
+                plist_path.as_str(),
+            ])
+            .quiet()
+            .run()?;
+        Ok(())
+    };
+    write_plist().with_context(|| plist::WritingPlist(plist_path.clone()))?;
 
-    let to_binary = format!("{}/{}", &output_path, bundle_name);
-    lib_path.cp(to_binary)?;
+    let binary_path = content_dir.join(bundle_name);
+    lib_path.cp(&binary_path)?;
 
     if let CrateType::Cdylib = crate_type {
+        let install_name = if versioned {
+            format!("@rpath/{bundle_name}.framework/Versions/A/{bundle_name}")
+        } else {
+            format!("@rpath/{bundle_name}.framework/{bundle_name}")
+        };
         sh.cmd("install_name_tool")
-            .args([
-                "-id",
-                &format!("@rpath/{}.framework/{}", bundle_name, bundle_name),
-                &format!("{}/{}", &output_path, bundle_name),
-            ])
+            .args(["-id", &install_name, binary_path.as_str()])
             .output()?;
     }
 
-    output_path.join("Headers").mkdirs()?;
-    output_path.join("Modules").mkdirs()?;
+    content_dir.join("Headers").mkdirs()?;
+    content_dir.join("Modules").mkdirs()?;
 
-    for header_path in header_paths.iter() {
+    for header_path in contents.header_paths.iter() {
         let header_name = header_path.file_name().context("header path error")?;
-        header_path.cp(output_path.join("Headers").join(header_name))?;
+        header_path.cp(content_dir.join("Headers").join(header_name))?;
+    }
+
+    let module_dest = content_dir.join("Modules").join("module.modulemap");
+    contents.module_path.cp(module_dest)?;
+
+    for resource in &contents.resources {
+        let dest = resources_dir.join(&resource.destination);
+        if let Some(parent) = dest.parent() {
+            parent.mkdirs()?;
+        }
+        resource.source.cp(dest)?;
     }
 
-    let module_dest = output_path.join("Modules").join("module.modulemap");
-    module_path.cp(module_dest)?;
+    if versioned {
+        symlink("A", &output_path.join("Versions").join("Current"))?;
+        for name in [bundle_name, "Headers", "Modules", "Resources"] {
+            symlink(&format!("Versions/Current/{name}"), &output_path.join(name))?;
+        }
+    }
 
-    println!(
-        "✅ Wrapped artifacts as framework success, output:\n{}",
-        output_path
-    );
     Ok(output_path)
 }
 
+/// The path of the framework [`wrap_as_framework`] creates in `output_dir`.
+pub fn framework_path(
+    platform: &ApplePlatform,
+    bundle_name: &str,
+    output_dir: &Utf8Path,
+) -> Utf8PathBuf {
+    output_dir
+        .join(format!("{:?}", platform))
+        .join(format!("{bundle_name}.framework"))
+}
+
+/// Create a relative symlink at `link` pointing to `target`.
+#[cfg(unix)]
+fn symlink(target: &str, link: &Utf8Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &str, link: &Utf8Path) -> std::io::Result<()> {
+    Err(std::io::Error::other(format!(
+        "Can't create the framework symlink {link} on this platform"
+    )))
+}
+
 /// Create an XCFramework from the frameworks.
 pub fn create_xcframework(
     framework_paths: Vec<Utf8PathBuf>,
@@ -148,8 +313,6 @@ pub fn create_xcframework(
 ) -> anyhow::Result<Utf8PathBuf> {
     const SUFFIX: &str = ".xcframework";
 
-    println!("🧰 Running create xcframework...");
-
     let sh = Shell::new()?;
 
     let xcframework_path = output_dir.join(format!("{}{}", bundle_name, SUFFIX));
@@ -164,32 +327,24 @@ pub fn create_xcframework(
     }
     cmd = cmd.args(["-output", xcframework_path.as_str()]);
 
-    cmd.run()?;
-    println!("✅ Run create xcframework success, output:\n{xcframework_path}");
+    cmd.quiet().run()?;
 
     Ok(xcframework_path)
 }
 
-/// Compress the XCFramework as a zip file.
+/// Compress the XCFramework into an archive of the given format.
 pub fn compress_xcframework(
     xcframework_path: &Utf8PathBuf,
     output_dir: &Utf8PathBuf,
-) -> anyhow::Result<Utf8PathBuf> {
-    println!("📦 Compressing XCFramework ...");
-
+    format: archive::ArchiveFormat,
+    compression_level: Option<u32>,
+) -> anyhow::Result<archive::Archive> {
     let framework_name = xcframework_path
         .file_name()
         .context("Missing xcframework name")?;
 
-    let dest = output_dir.join(format!("{}.zip", framework_name));
-    let source = xcframework_path;
-    zip_extensions::zip_writer::zip_create_from_directory(
-        &dest.clone().into_std_path_buf(),
-        &source.clone().into_std_path_buf(),
-    )?;
-
-    println!("✅ Compressed XCFramework success, output:\n{dest}");
-    Ok(dest)
+    let dest = output_dir.join(format!("{framework_name}.{}", format.extension()));
+    archive::archive_directory(xcframework_path, &dest, format, compression_level)
 }
 
 #[cfg(test)]
@@ -207,9 +362,13 @@ mod tests {
 
         // SAFETY: test-only, single-threaded test runner
         unsafe { std::env::set_var(env_var, "15.0") };
-        let result = resolve_deployment_target(&platform);
+        let result = resolve_deployment_target(&platform, &BTreeMap::new());
         assert_eq!(result, "15.0");
 
+        // The cargo environment of the platform wins
+        let env = BTreeMap::from([(env_var.to_string(), "16.0".to_string())]);
+        assert_eq!(resolve_deployment_target(&platform, &env), "16.0");
+
         // Restore original state
         unsafe {
             match original {
@@ -229,7 +388,7 @@ mod tests {
 
         // SAFETY: test-only, single-threaded test runner
         unsafe { std::env::remove_var(env_var) };
-        let result = resolve_deployment_target(&platform);
+        let result = resolve_deployment_target(&platform, &BTreeMap::new());
         assert_eq!(result, "5.0");
 
         // Restore original state
@@ -238,6 +397,73 @@ mod tests {
         }
     }
 
+    #[test]
+    fn libraries_with_foreign_objects_are_rejected() {
+        use macho::testing::*;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let platform = ApplePlatform::TvOS(EnvironmentWithoutCatalyst::Simulator);
+
+        // arm64 simulator objects default to a newer deployment target
+        let good = dir.join("libgood.a");
+        good.write(fat(&[
+            archive(&[("lib.o", object(ARM64, 8, (14, 0)))]),
+            archive(&[("lib.o", object(X86_64, 8, (10, 0)))]),
+        ]))
+        .unwrap();
+        let deployment_target = resolve_deployment_target(&platform, &BTreeMap::new());
+        validate_library(&platform, &good, &deployment_target).unwrap();
+
+        let bad = dir.join("libbad.a");
+        bad.write(archive(&[
+            ("lib.o", object(ARM64, 8, (14, 0))),
+            ("cc-dep.o", object(ARM64, 1, (11, 0))),
+        ]))
+        .unwrap();
+        let err = validate_library(&platform, &bad, &deployment_target)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("cc-dep.o (arm64) is built for macOS instead of tvOS Simulator"),
+            "{err}"
+        );
+        assert!(!err.contains("lib.o"), "{err}");
+    }
+
+    #[test]
+    fn resources_keep_paths_below_the_glob_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        dir.join("assets/certs/ca.pem").write("ca").unwrap();
+        dir.join("assets/certs/extra/leaf.pem")
+            .write("leaf")
+            .unwrap();
+        dir.join("assets/readme.txt").write("readme").unwrap();
+        dir.join("data/model.bin").write("model").unwrap();
+
+        let destinations = |pattern: &str| {
+            BundleResource::from_glob(dir.join(pattern).as_str())
+                .unwrap()
+                .into_iter()
+                .map(|r| r.destination.into_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            destinations("assets/**/*.pem"),
+            ["certs/ca.pem", "certs/extra/leaf.pem"]
+        );
+        assert_eq!(destinations("data/model.bin"), ["model.bin"]);
+        // Braces are matched literally by the glob crate
+        assert_eq!(glob_base("assets/{a,b}.png"), "assets");
+        assert_eq!(
+            destinations("assets/certs"),
+            ["certs/ca.pem", "certs/extra/leaf.pem"]
+        );
+        assert!(BundleResource::from_glob(dir.join("missing/*").as_str()).is_err());
+    }
+
     #[test]
     fn query_sdk_version_returns_version_string() {
         let sh = Shell::new().unwrap();
@@ -0,0 +1,453 @@
+//! Archives of the built XCFramework.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use camino_fs::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::{CompressionMethod, DateTime, ZipWriter, write::SimpleFileOptions};
+
+use super::reproducible;
+
+/// The supported archive formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum ArchiveFormat {
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+
+    /// The highest compression level of the format.
+    pub fn max_compression_level(&self) -> u32 {
+        match self {
+            ArchiveFormat::Zip | ArchiveFormat::TarGz => 9,
+            ArchiveFormat::TarZst => 22,
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zip" => Ok(ArchiveFormat::Zip),
+            "tar.gz" => Ok(ArchiveFormat::TarGz),
+            "tar.zst" => Ok(ArchiveFormat::TarZst),
+            _ => Err(format!("Unknown archive format: {s}")),
+        }
+    }
+}
+
+impl Display for ArchiveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// An archive produced from the XCFramework.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Archive {
+    pub format: ArchiveFormat,
+    pub path: Utf8PathBuf,
+    /// The hex encoded SHA-256 of the archive, as used by SwiftPM binary targets
+    pub checksum: String,
+}
+
+/// Archive the directory `source` into `dest` with the given format.
+///
+/// All formats keep symlinks and are reproducible, see [`zip_directory`].
+pub fn archive_directory(
+    source: &Utf8Path,
+    dest: &Utf8Path,
+    format: ArchiveFormat,
+    compression_level: Option<u32>,
+) -> Result<Archive> {
+    match format {
+        ArchiveFormat::Zip => zip_directory(source, dest, compression_level)?,
+        ArchiveFormat::TarGz => {
+            let level = flate2::Compression::new(compression_level.unwrap_or(6));
+            let encoder = flate2::GzBuilder::new().write(File::create(dest)?, level);
+            tar_directory(source, encoder)?.finish()?;
+        }
+        ArchiveFormat::TarZst => {
+            // zstd has no uncompressed level and reads 0 as its default level,
+            // so 0 selects its fastest level instead
+            let level = match compression_level {
+                None => zstd::DEFAULT_COMPRESSION_LEVEL,
+                Some(0) => *zstd::compression_level_range().start(),
+                Some(level) => level as i32,
+            };
+            let encoder = zstd::Encoder::new(File::create(dest)?, level)?;
+            tar_directory(source, encoder)?.finish()?;
+        }
+    }
+    Ok(Archive {
+        format,
+        path: dest.to_path_buf(),
+        checksum: checksum(dest)?,
+    })
+}
+
+/// The hex encoded SHA-256 of a file.
+pub fn checksum(path: &Utf8Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Files created by macOS that should never end up in an archive.
+const JUNK_FILES: &[&str] = &[".DS_Store", "__MACOSX", "Icon\r"];
+
+/// An entry of the directory tree to archive.
+struct Entry {
+    path: Utf8PathBuf,
+    /// The path inside the archive
+    name: String,
+    kind: EntryKind,
+    mode: u32,
+}
+
+enum EntryKind {
+    Dir,
+    File,
+    Symlink(Utf8PathBuf),
+}
+
+/// Zip the directory `source` into the archive `dest`.
+///
+/// The directory itself is the root of the archive, so `MyLib.xcframework`
+/// is unpacked as `MyLib.xcframework/...` as SwiftPM expects. Symlinks are
+/// stored as symlink entries instead of being followed, which keeps the
+/// versioned layout of macOS frameworks intact, and unix permissions are kept.
+///
+/// The archive is reproducible: entries are sorted, permissions normalized
+/// and all timestamps are set to `SOURCE_DATE_EPOCH`.
+///
+/// The compression level goes from 0 (stored) to 9, and defaults to 6.
+pub fn zip_directory(
+    source: &Utf8Path,
+    dest: &Utf8Path,
+    compression_level: Option<u32>,
+) -> Result<()> {
+    let options = match compression_level {
+        Some(0) => SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        level => SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(level.map(i64::from)),
+    }
+    .last_modified_time(zip_timestamp());
+
+    let mut zip = ZipWriter::new(File::create(dest)?);
+    for entry in collect_entries(source)? {
+        let options = options.unix_permissions(entry.mode);
+        match &entry.kind {
+            EntryKind::Dir => zip.add_directory(format!("{}/", entry.name), options)?,
+            EntryKind::Symlink(target) => zip.add_symlink(&entry.name, target, options)?,
+            EntryKind::File => {
+                zip.start_file(&entry.name, options)?;
+                io::copy(&mut File::open(&entry.path)?, &mut zip)?;
+            }
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Write the directory `source` as a tar stream, with the same layout and
+/// reproducibility guarantees as [`zip_directory`].
+fn tar_directory<W: Write>(source: &Utf8Path, writer: W) -> Result<W> {
+    let mtime = reproducible::source_date_epoch();
+    let mut tar = tar::Builder::new(writer);
+    for entry in collect_entries(source)? {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(entry.mode);
+        header.set_mtime(mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        match &entry.kind {
+            EntryKind::Dir => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_size(0);
+                tar.append_data(&mut header, format!("{}/", entry.name), io::empty())?;
+            }
+            EntryKind::Symlink(target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                tar.append_link(&mut header, &entry.name, target)?;
+            }
+            EntryKind::File => {
+                let file = File::open(&entry.path)?;
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(file.metadata()?.len());
+                tar.append_data(&mut header, &entry.name, file)?;
+            }
+        }
+    }
+    Ok(tar.into_inner()?)
+}
+
+/// `SOURCE_DATE_EPOCH` clamped to the range a zip archive can represent.
+fn zip_timestamp() -> DateTime {
+    let (year, month, day, hour, minute, second) =
+        reproducible::utc_date_time(reproducible::source_date_epoch());
+    if year < 1980 {
+        return DateTime::default();
+    }
+    DateTime::from_date_and_time(year.min(2107), month, day, hour, minute, second)
+        .unwrap_or_default()
+}
+
+/// List `source` and everything below it sorted by name, skipping junk files.
+fn collect_entries(source: &Utf8Path) -> Result<Vec<Entry>> {
+    let root = source.file_name().context("Missing directory name")?;
+
+    let mut entries = vec![];
+    let mut pending = vec![(source.to_path_buf(), root.to_string())];
+    while let Some((path, name)) = pending.pop() {
+        let metadata = path.symlink_metadata()?;
+        let file_type = metadata.file_type();
+        let mut mode = reproducible::normalized_mode(file_type.is_dir(), file_mode(&metadata));
+        let kind = if file_type.is_symlink() {
+            mode = 0o777;
+            EntryKind::Symlink(path.read_link_utf8()?)
+        } else if file_type.is_dir() {
+            for child in path.read_dir_utf8()? {
+                let child = child?;
+                if is_junk(child.file_name()) {
+                    continue;
+                }
+                let child_name = format!("{name}/{}", child.file_name());
+                pending.push((child.into_path(), child_name));
+            }
+            EntryKind::Dir
+        } else {
+            EntryKind::File
+        };
+        entries.push(Entry {
+            mode,
+            path,
+            name,
+            kind,
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+fn is_junk(file_name: &str) -> bool {
+    JUNK_FILES.contains(&file_name) || file_name.starts_with("._")
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.is_dir() { 0o755 } else { 0o644 }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn macos_xcframework(dir: &Utf8Path) -> Utf8PathBuf {
+        let xcframework = dir.join("A.xcframework");
+        let framework = xcframework.join("macos/A.framework");
+        framework.join("Versions/A/A").write("binary").unwrap();
+        std::os::unix::fs::symlink("A", framework.join("Versions/Current")).unwrap();
+        std::os::unix::fs::symlink("Versions/Current/A", framework.join("A")).unwrap();
+        xcframework
+    }
+
+    #[test]
+    fn symlinks_are_stored_as_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let xcframework = macos_xcframework(&dir);
+
+        let dest = dir.join("A.xcframework.zip");
+        zip_directory(&xcframework, &dest, None).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&dest).unwrap()).unwrap();
+        let mut read = |name: &str| {
+            let mut entry = archive.by_name(name).unwrap();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            (entry.is_symlink(), content)
+        };
+        assert_eq!(
+            read("A.xcframework/macos/A.framework/Versions/A/A"),
+            (false, "binary".into())
+        );
+        assert_eq!(
+            read("A.xcframework/macos/A.framework/Versions/Current"),
+            (true, "A".into())
+        );
+        assert_eq!(
+            read("A.xcframework/macos/A.framework/A"),
+            (true, "Versions/Current/A".into())
+        );
+    }
+
+    #[test]
+    fn permissions_are_kept_and_junk_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let xcframework = macos_xcframework(&dir);
+        let binary = xcframework.join("macos/A.framework/Versions/A/A");
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+        xcframework.join(".DS_Store").write("junk").unwrap();
+        xcframework
+            .join("macos/._A.framework")
+            .write("junk")
+            .unwrap();
+
+        let dest = dir.join("A.xcframework.zip");
+        zip_directory(&xcframework, &dest, Some(0)).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&dest).unwrap()).unwrap();
+        assert!(archive.file_names().all(|name| !name.contains("._")
+            && !name.contains(".DS_Store")
+            && name.starts_with("A.xcframework/")));
+        let entry = archive
+            .by_name("A.xcframework/macos/A.framework/Versions/A/A")
+            .unwrap();
+        assert_eq!(entry.unix_mode().unwrap() & 0o777, 0o755);
+        assert_eq!(entry.compression(), CompressionMethod::Stored);
+    }
+
+    #[test]
+    fn archives_are_reproducible() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let xcframework = macos_xcframework(&dir);
+        let header = xcframework.join("macos/A.framework/Versions/A/Headers/a.h");
+        header.write("int a;").unwrap();
+
+        let first = dir.join("first.zip");
+        zip_directory(&xcframework, &first, None).unwrap();
+
+        // Touch and change permissions, which should not affect the archive
+        std::fs::set_permissions(&header, std::fs::Permissions::from_mode(0o600)).unwrap();
+        header.write("int a;").unwrap();
+        let second = dir.join("second.zip");
+        zip_directory(&xcframework, &second, None).unwrap();
+
+        assert_eq!(first.read_bytes().unwrap(), second.read_bytes().unwrap());
+
+        let mut archive = zip::ZipArchive::new(File::open(&first).unwrap()).unwrap();
+        let names = archive.file_names().map(String::from).collect::<Vec<_>>();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+        let entry = archive.by_index(0).unwrap();
+        assert_eq!(entry.last_modified(), Some(DateTime::default()));
+    }
+
+    #[test]
+    fn tarballs_keep_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let xcframework = macos_xcframework(&dir);
+
+        for format in [ArchiveFormat::TarGz, ArchiveFormat::TarZst] {
+            let dest = dir.join(format!("A.xcframework.{format}"));
+            let archive = archive_directory(&xcframework, &dest, format, None).unwrap();
+            assert_eq!(archive.checksum, checksum(&dest).unwrap());
+            assert_eq!(archive.checksum.len(), 64);
+
+            let reader: Box<dyn io::Read> = match format {
+                ArchiveFormat::TarGz => {
+                    Box::new(flate2::read::GzDecoder::new(File::open(&dest).unwrap()))
+                }
+                _ => Box::new(zstd::Decoder::new(File::open(&dest).unwrap()).unwrap()),
+            };
+            let mut tar = tar::Archive::new(reader);
+            let symlinks = tar
+                .entries()
+                .unwrap()
+                .map(|e| e.unwrap())
+                .filter(|e| e.header().entry_type().is_symlink())
+                .map(|e| {
+                    (
+                        e.path().unwrap().display().to_string(),
+                        e.link_name().unwrap().unwrap().display().to_string(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                symlinks,
+                [
+                    (
+                        "A.xcframework/macos/A.framework/A".to_string(),
+                        "Versions/Current/A".to_string()
+                    ),
+                    (
+                        "A.xcframework/macos/A.framework/Versions/Current".to_string(),
+                        "A".to_string()
+                    ),
+                ]
+            );
+
+            // Archiving again gives the same checksum
+            let again = archive_directory(&xcframework, &dest, format, None).unwrap();
+            assert_eq!(archive.checksum, again.checksum);
+        }
+    }
+
+    #[test]
+    fn zstd_level_zero_is_the_fastest_level() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let xcframework = dir.join("A.xcframework");
+        xcframework
+            .join("ios-arm64/A.framework/A")
+            .write("binary ".repeat(10_000))
+            .unwrap();
+
+        let size = |level| {
+            let dest = dir.join(format!("A-{level:?}.xcframework.tar.zst"));
+            archive_directory(&xcframework, &dest, ArchiveFormat::TarZst, level).unwrap();
+            dest.metadata().unwrap().len()
+        };
+        assert!(size(Some(0)) > size(None));
+        assert!(size(None) >= size(Some(19)));
+    }
+
+    #[test]
+    fn checksum_is_sha256() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.path().join("empty")).unwrap();
+        path.write("").unwrap();
+        assert_eq!(
+            checksum(&path).unwrap(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
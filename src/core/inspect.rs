@@ -0,0 +1,525 @@
+//! A summary of the slices of an existing XCFramework, read either from the
+//! bundle directory or directly from a zip archive.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use camino_fs::*;
+use plist::Value;
+use serde::Serialize;
+
+use super::macho;
+use super::modulemap::parse_module_name;
+use super::platform::ApplePlatform;
+
+/// The contents of an XCFramework.
+#[derive(Debug, Serialize)]
+pub struct Inspection {
+    pub libraries: Vec<Slice>,
+}
+
+/// One library of an XCFramework.
+#[derive(Debug, Serialize)]
+pub struct Slice {
+    /// The `LibraryIdentifier`, like `ios-arm64_x86_64-simulator`
+    pub identifier: String,
+    /// The `SupportedPlatform`, like `ios` or `macos`
+    pub platform: String,
+    /// The `SupportedPlatformVariant`, like `simulator` or `maccatalyst`
+    pub variant: Option<String>,
+    /// The architectures found in the binary
+    pub architectures: Vec<String>,
+    /// The minimum OS version per architecture
+    pub min_os: BTreeMap<String, String>,
+    pub kind: BinaryKind,
+    /// The binary, relative to the slice directory
+    pub binary: String,
+    /// The install name of a dynamic library
+    pub install_name: Option<String>,
+    /// The size of the binary in bytes
+    pub size: u64,
+    /// The module declared in the modulemap
+    pub module: Option<String>,
+    /// The headers, relative to the headers directory
+    pub headers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryKind {
+    Static,
+    Dynamic,
+}
+
+impl fmt::Display for BinaryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BinaryKind::Static => "static",
+            BinaryKind::Dynamic => "dynamic",
+        })
+    }
+}
+
+/// Inspect an XCFramework directory or a zip archive containing one.
+pub fn inspect(path: &Utf8Path) -> Result<Inspection> {
+    let bundle = Bundle::open(path)?;
+    let info = bundle
+        .read("Info.plist")
+        .with_context(|| format!("{path} has no Info.plist"))?;
+    let info = Value::from_reader(std::io::Cursor::new(info)).context("reading Info.plist")?;
+    let libraries = info
+        .as_dictionary()
+        .and_then(|info| info.get("AvailableLibraries"))
+        .and_then(Value::as_array)
+        .context("Info.plist has no AvailableLibraries")?;
+
+    let mut slices = libraries
+        .iter()
+        .map(|library| {
+            let library = library
+                .as_dictionary()
+                .context("AvailableLibraries contains a non-dictionary entry")?;
+            let string = |key: &str| library.get(key).and_then(Value::as_string);
+            let identifier = string("LibraryIdentifier").context("missing LibraryIdentifier")?;
+            inspect_slice(&bundle, identifier, string)
+                .with_context(|| format!("inspecting {identifier}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    slices.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+    Ok(Inspection { libraries: slices })
+}
+
+fn inspect_slice<'a>(
+    bundle: &Bundle,
+    identifier: &str,
+    string: impl Fn(&str) -> Option<&'a str>,
+) -> Result<Slice> {
+    let library_path = string("LibraryPath").context("missing LibraryPath")?;
+
+    let (binary, headers_dir, modulemap) = if library_path.ends_with(".framework") {
+        let framework = format!("{identifier}/{library_path}");
+        let info = ["Resources/Info.plist", "Info.plist"]
+            .iter()
+            .find_map(|p| bundle.read(&format!("{framework}/{p}")))
+            .with_context(|| format!("{library_path} has no Info.plist"))?;
+        let info = Value::from_reader(std::io::Cursor::new(info))?;
+        let executable = info
+            .as_dictionary()
+            .and_then(|info| info.get("CFBundleExecutable"))
+            .and_then(Value::as_string)
+            .context("missing CFBundleExecutable")?;
+        (
+            format!("{library_path}/{executable}"),
+            Some(format!("{framework}/Headers")),
+            Some(format!("{framework}/Modules/module.modulemap")),
+        )
+    } else {
+        let headers_dir = string("HeadersPath").map(|p| format!("{identifier}/{p}"));
+        let modulemap = headers_dir
+            .as_ref()
+            .map(|d| format!("{d}/module.modulemap"));
+        (library_path.to_string(), headers_dir, modulemap)
+    };
+
+    let data = bundle
+        .read(&format!("{identifier}/{binary}"))
+        .with_context(|| format!("missing binary {binary}"))?;
+    let objects = macho::read_objects(&data).with_context(|| format!("reading {binary}"))?;
+
+    let mut architectures = vec![];
+    let mut min_os = BTreeMap::new();
+    for object in &objects {
+        if !architectures.contains(&object.arch) {
+            architectures.push(object.arch.clone());
+        }
+        if let Some(version) = &object.min_os {
+            min_os
+                .entry(object.arch.clone())
+                .or_insert_with(|| version.clone());
+        }
+    }
+    let dylib = objects.iter().find(|o| o.is_dylib);
+
+    let module = modulemap
+        .and_then(|path| bundle.read(&path))
+        .and_then(|content| parse_module_name(&String::from_utf8_lossy(&content)).ok());
+    let headers = headers_dir
+        .map(|dir| bundle.files(&dir))
+        .unwrap_or_default();
+
+    Ok(Slice {
+        identifier: identifier.to_string(),
+        platform: string("SupportedPlatform").unwrap_or_default().to_string(),
+        variant: string("SupportedPlatformVariant").map(str::to_string),
+        architectures,
+        min_os,
+        kind: if dylib.is_some() {
+            BinaryKind::Dynamic
+        } else {
+            BinaryKind::Static
+        },
+        binary,
+        install_name: dylib.and_then(|o| o.install_name.clone()),
+        size: data.len() as u64,
+        module,
+        headers,
+    })
+}
+
+impl Slice {
+    fn platform_display_name(&self) -> String {
+        ApplePlatform::from_supported_platform(&self.platform, self.variant.as_deref())
+            .map(|p| p.platform_display_name().to_string())
+            .unwrap_or_else(|| self.platform.clone())
+    }
+
+    fn min_os_summary(&self) -> String {
+        let versions = self.min_os.values().collect::<BTreeSet<_>>();
+        match versions.into_iter().collect::<Vec<_>>().as_slice() {
+            [] => "-".into(),
+            [version] => version.to_string(),
+            _ => self
+                .min_os
+                .iter()
+                .map(|(arch, version)| format!("{arch} {version}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = [
+            "SLICE",
+            "PLATFORM",
+            "ARCHITECTURES",
+            "MIN OS",
+            "KIND",
+            "SIZE",
+            "MODULE",
+            "HEADERS",
+        ];
+        let rows = self
+            .libraries
+            .iter()
+            .map(|slice| {
+                [
+                    slice.identifier.clone(),
+                    slice.platform_display_name(),
+                    slice.architectures.join(", "),
+                    slice.min_os_summary(),
+                    slice.kind.to_string(),
+                    format_size(slice.size),
+                    slice.module.clone().unwrap_or_else(|| "-".into()),
+                    slice.headers.len().to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let mut widths = header.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let header = header.map(str::to_string);
+        for row in std::iter::once(&header).chain(&rows) {
+            let line = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        for slice in &self.libraries {
+            writeln!(f)?;
+            writeln!(f, "{}: {}", slice.identifier, slice.binary)?;
+            if let Some(install_name) = &slice.install_name {
+                writeln!(f, "  install name: {install_name}")?;
+            }
+            for header in &slice.headers {
+                writeln!(f, "  {header}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{size} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// The files of an XCFramework, either a directory or a zip archive.
+enum Bundle {
+    Dir(Utf8PathBuf),
+    /// The entries below the XCFramework directory of the archive
+    Zip(BTreeMap<String, ZipEntry>),
+}
+
+enum ZipEntry {
+    File(Vec<u8>),
+    Symlink(String),
+}
+
+impl Bundle {
+    fn open(path: &Utf8Path) -> Result<Self> {
+        if path.is_dir() {
+            return Ok(Self::Dir(path.to_path_buf()));
+        }
+        let mut archive = zip::ZipArchive::new(File::open(path)?)
+            .with_context(|| format!("{path} is neither a directory nor a zip archive"))?;
+
+        // The XCFramework is the directory holding the top-level Info.plist
+        let root = archive
+            .file_names()
+            .filter_map(|name| name.strip_suffix("Info.plist"))
+            .filter(|dir| dir.is_empty() || dir.ends_with(".xcframework/"))
+            .min_by_key(|dir| dir.len())
+            .with_context(|| format!("no XCFramework found in {path}"))?
+            .to_string();
+
+        let mut entries = BTreeMap::new();
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            let Some(name) = entry.name().strip_prefix(root.as_str()) else {
+                continue;
+            };
+            if name.is_empty() || entry.is_dir() {
+                continue;
+            }
+            let name = name.to_string();
+            let mut content = vec![];
+            entry.read_to_end(&mut content)?;
+            let entry = if entry.is_symlink() {
+                ZipEntry::Symlink(String::from_utf8(content)?)
+            } else {
+                ZipEntry::File(content)
+            };
+            entries.insert(name, entry);
+        }
+        Ok(Self::Zip(entries))
+    }
+
+    /// Read a file, following symlinks.
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        match self {
+            Self::Dir(root) => root.join(path).read_bytes().ok(),
+            Self::Zip(entries) => match entries.get(&self.resolve(path)?)? {
+                ZipEntry::File(content) => Some(content.clone()),
+                ZipEntry::Symlink(_) => None,
+            },
+        }
+    }
+
+    /// The files below a directory, relative to it and sorted.
+    fn files(&self, dir: &str) -> Vec<String> {
+        let mut files = match self {
+            Self::Dir(root) => {
+                let dir = root.join(dir);
+                dir.ls()
+                    .recurse()
+                    .files()
+                    .filter_map(|p| p.strip_prefix(&dir).ok().map(|p| p.to_string()))
+                    .collect()
+            }
+            Self::Zip(entries) => {
+                let Some(dir) = self.resolve(dir) else {
+                    return vec![];
+                };
+                let prefix = format!("{dir}/");
+                entries
+                    .iter()
+                    .filter(|(_, entry)| matches!(entry, ZipEntry::File(_)))
+                    .filter_map(|(name, _)| name.strip_prefix(&prefix).map(str::to_string))
+                    .collect::<Vec<_>>()
+            }
+        };
+        files.sort();
+        files
+    }
+
+    /// Resolve the symlinks in an archive path.
+    fn resolve(&self, path: &str) -> Option<String> {
+        let Self::Zip(entries) = self else {
+            return Some(path.to_string());
+        };
+        let mut pending = path.split('/').map(str::to_string).collect::<Vec<_>>();
+        pending.reverse();
+        let mut resolved: Vec<String> = vec![];
+        let mut hops = 0;
+        while let Some(component) = pending.pop() {
+            match component.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    resolved.pop()?;
+                    continue;
+                }
+                _ => {}
+            }
+            resolved.push(component);
+            if let Some(ZipEntry::Symlink(target)) = entries.get(&resolved.join("/")) {
+                hops += 1;
+                if hops > 40 {
+                    return None;
+                }
+                resolved.pop();
+                pending.extend(target.rsplit('/').map(str::to_string));
+            }
+        }
+        Some(resolved.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::archive::zip_directory;
+    use crate::core::macho::testing::*;
+    use plist::Dictionary;
+
+    /// An XCFramework with a static iOS slice and a dynamic macOS framework
+    /// using the versioned bundle layout.
+    fn xcframework(dir: &Utf8Path) -> Utf8PathBuf {
+        let root = dir.join("MyMath.xcframework");
+        root.mkdirs().unwrap();
+        let mut libraries = vec![];
+        for (identifier, platform, archs) in [
+            ("ios-arm64", "ios", vec!["arm64"]),
+            ("macos-arm64_x86_64", "macos", vec!["arm64", "x86_64"]),
+        ] {
+            let mut library = Dictionary::new();
+            library.insert("LibraryIdentifier".into(), identifier.into());
+            library.insert("LibraryPath".into(), "MyMath.framework".into());
+            library.insert("SupportedPlatform".into(), platform.into());
+            library.insert(
+                "SupportedArchitectures".into(),
+                Value::Array(archs.into_iter().map(Value::from).collect()),
+            );
+            libraries.push(Value::Dictionary(library));
+        }
+        let mut info = Dictionary::new();
+        info.insert("AvailableLibraries".into(), Value::Array(libraries));
+        Value::Dictionary(info)
+            .to_file_xml(root.join("Info.plist"))
+            .unwrap();
+
+        let mut framework_info = Dictionary::new();
+        framework_info.insert("CFBundleExecutable".into(), "MyMath".into());
+        let framework_info = Value::Dictionary(framework_info);
+        let modulemap = "framework module MyMath {\n  umbrella header \"mymath.h\"\n}\n";
+
+        let ios = root.join("ios-arm64/MyMath.framework");
+        ios.join("MyMath")
+            .write(archive(&[("a.o", object(ARM64, 2, (15, 0)))]))
+            .unwrap();
+        ios.join("Headers/mymath.h").write("").unwrap();
+        ios.join("Modules/module.modulemap")
+            .write(modulemap)
+            .unwrap();
+        framework_info.to_file_xml(ios.join("Info.plist")).unwrap();
+
+        let macos = root.join("macos-arm64_x86_64/MyMath.framework");
+        let version = macos.join("Versions/A");
+        let install_name = "@rpath/MyMath.framework/Versions/A/MyMath";
+        version
+            .join("MyMath")
+            .write(fat(&[
+                dylib(ARM64, 1, install_name),
+                dylib(X86_64, 1, install_name),
+            ]))
+            .unwrap();
+        version.join("Headers/mymath.h").write("").unwrap();
+        version
+            .join("Modules/module.modulemap")
+            .write(modulemap)
+            .unwrap();
+        version.join("Resources").mkdirs().unwrap();
+        framework_info
+            .to_file_xml(version.join("Resources/Info.plist"))
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            symlink("A", macos.join("Versions/Current")).unwrap();
+            for name in ["MyMath", "Headers", "Modules", "Resources"] {
+                symlink(format!("Versions/Current/{name}"), macos.join(name)).unwrap();
+            }
+        }
+        root
+    }
+
+    fn check(inspection: &Inspection) {
+        let [ios, macos] = inspection.libraries.as_slice() else {
+            panic!("expected two slices");
+        };
+        assert_eq!(ios.kind, BinaryKind::Static);
+        assert_eq!(ios.architectures, ["arm64"]);
+        assert_eq!(ios.min_os["arm64"], "15.0");
+        assert_eq!(ios.module.as_deref(), Some("MyMath"));
+        assert_eq!(ios.headers, ["mymath.h"]);
+
+        assert_eq!(macos.kind, BinaryKind::Dynamic);
+        assert_eq!(macos.architectures, ["arm64", "x86_64"]);
+        assert_eq!(macos.binary, "MyMath.framework/MyMath");
+        assert_eq!(
+            macos.install_name.as_deref(),
+            Some("@rpath/MyMath.framework/Versions/A/MyMath")
+        );
+        assert_eq!(macos.module.as_deref(), Some("MyMath"));
+        assert_eq!(macos.headers, ["mymath.h"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn inspect_directory_and_zip() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let root = xcframework(&dir);
+        check(&inspect(&root).unwrap());
+
+        let zip = dir.join("MyMath.xcframework.zip");
+        zip_directory(&root, &zip, None).unwrap();
+        let inspection = inspect(&zip).unwrap();
+        check(&inspection);
+
+        let json = serde_json::to_value(&inspection).unwrap();
+        assert_eq!(json["libraries"][1]["kind"], "dynamic");
+        assert_eq!(json["libraries"][0]["min_os"]["arm64"], "15.0");
+
+        let table = inspection.to_string();
+        let lines = table.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("SLICE"), "{table}");
+        assert!(
+            lines[1].contains("iOS") && lines[1].contains("static"),
+            "{table}"
+        );
+        assert!(
+            lines[2].contains("arm64, x86_64") && lines[2].contains("11.0"),
+            "{table}"
+        );
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+    }
+}
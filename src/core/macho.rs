@@ -0,0 +1,505 @@
+//! A minimal reader for the Mach-O headers of Apple binaries: thin Mach-O
+//! files, universal (fat) binaries and static library archives.
+//!
+//! Reference: [mach-o/loader.h](https://github.com/apple-oss-distributions/xnu/blob/main/EXTERNAL_HEADERS/mach-o/loader.h)
+
+use anyhow::{Context, Result, bail};
+use camino_fs::*;
+
+use super::platform::{ApplePlatform, Environment, EnvironmentWithoutCatalyst};
+
+const MH_MAGIC: u32 = 0xfeed_face;
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const FAT_MAGIC: u32 = 0xcafe_babe;
+const FAT_MAGIC_64: u32 = 0xcafe_babf;
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+
+const MH_DYLIB: u32 = 6;
+
+const LC_ID_DYLIB: u32 = 0xd;
+const LC_VERSION_MIN_MACOSX: u32 = 0x24;
+const LC_VERSION_MIN_IPHONEOS: u32 = 0x25;
+const LC_VERSION_MIN_TVOS: u32 = 0x2f;
+const LC_VERSION_MIN_WATCHOS: u32 = 0x30;
+const LC_BUILD_VERSION: u32 = 0x32;
+
+/// An object file found in a binary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachObject {
+    /// The archive member the object was read from, for static libraries
+    pub member: Option<String>,
+    /// The architecture name as used by Xcode, like `arm64` or `x86_64`
+    pub arch: String,
+    /// The platform the object was built for, if it records one
+    pub platform: Option<ApplePlatform>,
+    /// The minimum OS version the object was built for
+    pub min_os: Option<String>,
+    /// Whether the object is a dynamic library
+    pub is_dylib: bool,
+    /// The install name of a dynamic library
+    pub install_name: Option<String>,
+}
+
+/// Read the objects of a Mach-O file, universal binary or static library.
+pub fn read_file(path: &Utf8Path) -> Result<Vec<MachObject>> {
+    let data = path.read_bytes()?;
+    read_objects(&data).with_context(|| format!("reading Mach-O binary {path}"))
+}
+
+/// Read the objects of a Mach-O file, universal binary or static library.
+pub fn read_objects(data: &[u8]) -> Result<Vec<MachObject>> {
+    if data.starts_with(AR_MAGIC) {
+        return read_archive(data);
+    }
+    match read_u32_be(data, 0)? {
+        FAT_MAGIC | FAT_MAGIC_64 => read_fat(data),
+        _ => Ok(vec![read_object(data)?]),
+    }
+}
+
+/// Check that an object was built for `platform`, and, when given, for at
+/// most the `deployment_target` version. Returns the problem found.
+pub fn check_object(
+    object: &MachObject,
+    platform: &ApplePlatform,
+    deployment_target: Option<&str>,
+) -> Option<String> {
+    let name = object.member.as_deref().unwrap_or("the binary");
+    let built_for = object.platform.as_ref()?;
+    if built_for != platform {
+        return Some(format!(
+            "{name} ({}) is built for {} instead of {}",
+            object.arch,
+            built_for.platform_display_name(),
+            platform.platform_display_name()
+        ));
+    }
+    let (Some(min_os), Some(deployment_target)) = (&object.min_os, deployment_target) else {
+        return None;
+    };
+    if compare_versions(min_os, deployment_target).is_gt() {
+        return Some(format!(
+            "{name} ({}) requires {} {min_os}, newer than the deployment target {deployment_target}",
+            object.arch,
+            platform.platform_display_name()
+        ));
+    }
+    None
+}
+
+/// Compare dotted version numbers, where missing components count as zero.
+pub fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let parse = |v: &str| {
+        v.split('.')
+            .map(|c| c.trim().parse::<u32>().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+    let (mut a, mut b) = (parse(a), parse(b));
+    let len = a.len().max(b.len());
+    a.resize(len, 0);
+    b.resize(len, 0);
+    a.cmp(&b)
+}
+
+fn read_fat(data: &[u8]) -> Result<Vec<MachObject>> {
+    let is_64 = read_u32_be(data, 0)? == FAT_MAGIC_64;
+    let count = read_u32_be(data, 4)? as usize;
+    let entry_size = if is_64 { 32 } else { 20 };
+
+    let mut objects = vec![];
+    for i in 0..count {
+        let entry = 8 + i * entry_size;
+        let (offset, size) = if is_64 {
+            (
+                read_u64_be(data, entry + 8)?,
+                read_u64_be(data, entry + 16)?,
+            )
+        } else {
+            (
+                read_u32_be(data, entry + 8)? as u64,
+                read_u32_be(data, entry + 12)? as u64,
+            )
+        };
+        let slice = slice(data, offset as usize, size as usize)?;
+        objects.extend(read_objects(slice)?);
+    }
+    Ok(objects)
+}
+
+fn read_archive(data: &[u8]) -> Result<Vec<MachObject>> {
+    const HEADER_SIZE: usize = 60;
+
+    let mut objects = vec![];
+    let mut long_names: &[u8] = &[];
+    let mut offset = AR_MAGIC.len();
+    while offset + HEADER_SIZE <= data.len() {
+        let header = &data[offset..offset + HEADER_SIZE];
+        let name = String::from_utf8_lossy(&header[..16])
+            .trim_end()
+            .to_string();
+        let size: usize = String::from_utf8_lossy(&header[48..58])
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid size of archive member {name}"))?;
+        let mut content = slice(data, offset + HEADER_SIZE, size)?;
+        offset += HEADER_SIZE + size + size % 2;
+
+        // BSD archives store long names after the header
+        let name = if let Some(len) = name.strip_prefix("#1/") {
+            let len: usize = len.parse().context("invalid archive member name length")?;
+            let name = slice(content, 0, len)?;
+            content = &content[len..];
+            String::from_utf8_lossy(name)
+                .trim_end_matches('\0')
+                .to_string()
+        } else if name == "//" {
+            long_names = content;
+            continue;
+        } else if let Some(index) = name.strip_prefix('/').and_then(|i| i.parse().ok()) {
+            let name = long_names.get(index..).unwrap_or_default();
+            let end = name.iter().position(|b| *b == b'\n').unwrap_or(name.len());
+            String::from_utf8_lossy(&name[..end])
+                .trim_end_matches('/')
+                .to_string()
+        } else {
+            name.trim_end_matches('/').to_string()
+        };
+
+        // Symbol tables and non Mach-O members, like LLVM bitcode, are skipped
+        if name.is_empty() || name.starts_with("__.SYMDEF") || !is_mach_o(content) {
+            continue;
+        }
+        let mut object = read_object(content).with_context(|| format!("reading {name}"))?;
+        object.member = Some(name);
+        objects.push(object);
+    }
+    Ok(objects)
+}
+
+fn is_mach_o(data: &[u8]) -> bool {
+    matches!(read_u32_le(data, 0), Ok(MH_MAGIC | MH_MAGIC_64))
+}
+
+fn read_object(data: &[u8]) -> Result<MachObject> {
+    let header_size = match read_u32_le(data, 0)? {
+        MH_MAGIC => 28,
+        MH_MAGIC_64 => 32,
+        magic => bail!("not a Mach-O file (magic {magic:#x})"),
+    };
+    let cpu_type = read_u32_le(data, 4)?;
+    let cpu_subtype = read_u32_le(data, 8)?;
+    let file_type = read_u32_le(data, 12)?;
+    let command_count = read_u32_le(data, 16)?;
+    let arch = arch_name(cpu_type, cpu_subtype);
+
+    let mut platform = None;
+    let mut min_os = None;
+    let mut install_name = None;
+    let mut offset = header_size;
+    for _ in 0..command_count {
+        let command = read_u32_le(data, offset)?;
+        let size = read_u32_le(data, offset + 4)? as usize;
+        match command {
+            LC_BUILD_VERSION => {
+                platform = build_platform(read_u32_le(data, offset + 8)?);
+                min_os = Some(format_version(read_u32_le(data, offset + 12)?));
+            }
+            LC_VERSION_MIN_MACOSX
+            | LC_VERSION_MIN_IPHONEOS
+            | LC_VERSION_MIN_TVOS
+            | LC_VERSION_MIN_WATCHOS => {
+                platform = version_min_platform(command, &arch);
+                min_os = Some(format_version(read_u32_le(data, offset + 8)?));
+            }
+            LC_ID_DYLIB => {
+                let name_offset = read_u32_le(data, offset + 8)? as usize;
+                let name = slice(data, offset + name_offset, size.saturating_sub(name_offset))?;
+                let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+                install_name = Some(String::from_utf8_lossy(&name[..end]).to_string());
+            }
+            _ => {}
+        }
+        if size == 0 {
+            bail!("invalid load command size");
+        }
+        offset += size;
+    }
+    Ok(MachObject {
+        member: None,
+        arch,
+        platform,
+        min_os,
+        is_dylib: file_type == MH_DYLIB,
+        install_name,
+    })
+}
+
+fn arch_name(cpu_type: u32, cpu_subtype: u32) -> String {
+    const CPU_ARCH_ABI64: u32 = 0x0100_0000;
+    const CPU_ARCH_ABI64_32: u32 = 0x0200_0000;
+    const CPU_TYPE_X86: u32 = 7;
+    const CPU_TYPE_ARM: u32 = 12;
+
+    match (cpu_type, cpu_subtype & 0xff) {
+        (CPU_TYPE_X86, _) => "i386".into(),
+        (t, _) if t == CPU_TYPE_X86 | CPU_ARCH_ABI64 => "x86_64".into(),
+        (t, 2) if t == CPU_TYPE_ARM | CPU_ARCH_ABI64 => "arm64e".into(),
+        (t, _) if t == CPU_TYPE_ARM | CPU_ARCH_ABI64 => "arm64".into(),
+        (t, _) if t == CPU_TYPE_ARM | CPU_ARCH_ABI64_32 => "arm64_32".into(),
+        (CPU_TYPE_ARM, 9) => "armv7".into(),
+        (CPU_TYPE_ARM, 11) => "armv7s".into(),
+        (CPU_TYPE_ARM, 12) => "armv7k".into(),
+        (t, s) => format!("cpu {t:#x}/{s}"),
+    }
+}
+
+/// The platform of an `LC_BUILD_VERSION` load command.
+fn build_platform(platform: u32) -> Option<ApplePlatform> {
+    Some(match platform {
+        1 => ApplePlatform::MacOS,
+        2 => ApplePlatform::IOS(Environment::Device),
+        3 => ApplePlatform::TvOS(EnvironmentWithoutCatalyst::Device),
+        4 => ApplePlatform::WatchOS(EnvironmentWithoutCatalyst::Device),
+        6 => ApplePlatform::IOS(Environment::Catalyst),
+        7 => ApplePlatform::IOS(Environment::Simulator),
+        8 => ApplePlatform::TvOS(EnvironmentWithoutCatalyst::Simulator),
+        9 => ApplePlatform::WatchOS(EnvironmentWithoutCatalyst::Simulator),
+        _ => return None,
+    })
+}
+
+/// The platform of the older `LC_VERSION_MIN_*` load commands, which don't
+/// distinguish simulators. Simulator binaries were the Intel ones.
+fn version_min_platform(command: u32, arch: &str) -> Option<ApplePlatform> {
+    let is_intel = matches!(arch, "x86_64" | "i386");
+    Some(match (command, is_intel) {
+        (LC_VERSION_MIN_MACOSX, _) => ApplePlatform::MacOS,
+        (LC_VERSION_MIN_IPHONEOS, false) => ApplePlatform::IOS(Environment::Device),
+        (LC_VERSION_MIN_IPHONEOS, true) => ApplePlatform::IOS(Environment::Simulator),
+        (LC_VERSION_MIN_TVOS, false) => ApplePlatform::TvOS(EnvironmentWithoutCatalyst::Device),
+        (LC_VERSION_MIN_TVOS, true) => ApplePlatform::TvOS(EnvironmentWithoutCatalyst::Simulator),
+        (LC_VERSION_MIN_WATCHOS, false) => {
+            ApplePlatform::WatchOS(EnvironmentWithoutCatalyst::Device)
+        }
+        (LC_VERSION_MIN_WATCHOS, true) => {
+            ApplePlatform::WatchOS(EnvironmentWithoutCatalyst::Simulator)
+        }
+        _ => return None,
+    })
+}
+
+/// Format a version encoded as `xxxx.yy.zz` nibbles.
+fn format_version(version: u32) -> String {
+    let (major, minor, patch) = (version >> 16, (version >> 8) & 0xff, version & 0xff);
+    if patch == 0 {
+        format!("{major}.{minor}")
+    } else {
+        format!("{major}.{minor}.{patch}")
+    }
+}
+
+fn slice(data: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    data.get(offset..offset.saturating_add(len))
+        .context("unexpected end of file")
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(slice(data, offset, 4)?.try_into()?))
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(slice(data, offset, 4)?.try_into()?))
+}
+
+fn read_u64_be(data: &[u8], offset: usize) -> Result<u64> {
+    Ok(u64::from_be_bytes(slice(data, offset, 8)?.try_into()?))
+}
+
+/// Builders for synthetic binaries, shared with the tests of other modules.
+#[cfg(test)]
+pub(crate) mod testing {
+    pub const ARM64: (u32, u32) = (0x0100_000c, 0);
+    pub const X86_64: (u32, u32) = (0x0100_0007, 3);
+
+    /// A 64-bit Mach-O object with an `LC_BUILD_VERSION` load command.
+    pub fn object(cpu: (u32, u32), platform: u32, min_os: (u32, u32)) -> Vec<u8> {
+        let mut data = vec![];
+        for value in [0xfeed_facf, cpu.0, cpu.1, 1, 1, 24, 0, 0] {
+            data.extend(u32::to_le_bytes(value));
+        }
+        let min_os = (min_os.0 << 16) | (min_os.1 << 8);
+        for value in [0x32, 24, platform, min_os, min_os, 0] {
+            data.extend(u32::to_le_bytes(value));
+        }
+        data
+    }
+
+    /// A 64-bit Mach-O dynamic library with the given install name.
+    pub fn dylib(cpu: (u32, u32), platform: u32, install_name: &str) -> Vec<u8> {
+        let mut data = object(cpu, platform, (11, 0));
+        let name_size = (install_name.len() + 8) & !7;
+        let command_size = 24 + name_size as u32;
+        data[12..16].copy_from_slice(&u32::to_le_bytes(6));
+        data[16..20].copy_from_slice(&u32::to_le_bytes(2));
+        data[20..24].copy_from_slice(&u32::to_le_bytes(24 + command_size));
+        for value in [0xd, command_size, 24, 0, 0, 0] {
+            data.extend(u32::to_le_bytes(value));
+        }
+        data.extend(install_name.as_bytes());
+        data.resize(data.len() + name_size - install_name.len(), 0);
+        data
+    }
+
+    /// A universal binary with the given slices.
+    pub fn fat(slices: &[Vec<u8>]) -> Vec<u8> {
+        let mut header = vec![];
+        header.extend(u32::to_be_bytes(0xcafe_babe));
+        header.extend(u32::to_be_bytes(slices.len() as u32));
+        let mut offset = 8 + 20 * slices.len();
+        let mut body: Vec<u8> = vec![];
+        for slice in slices {
+            for value in [0, 0, offset as u32, slice.len() as u32, 0] {
+                header.extend(u32::to_be_bytes(value));
+            }
+            body.extend_from_slice(slice);
+            offset += slice.len();
+        }
+        header.extend(body);
+        header
+    }
+
+    /// A BSD static library archive with the given members.
+    pub fn archive(members: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut data = b"!<arch>\n".to_vec();
+        let symdef = ("__.SYMDEF", vec![0; 8]);
+        for (name, content) in std::iter::once(&symdef).chain(members) {
+            let size = name.len() + content.len();
+            let header = format!(
+                "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                format!("#1/{}", name.len()),
+                0,
+                0,
+                0,
+                644,
+                size
+            );
+            data.extend(header.as_bytes());
+            data.extend(name.as_bytes());
+            data.extend(content);
+            if size % 2 == 1 {
+                data.push(b'\n');
+            }
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+
+    #[test]
+    fn thin_object() {
+        let objects = read_objects(&object(ARM64, 7, (14, 0))).unwrap();
+        assert_eq!(
+            objects,
+            [MachObject {
+                member: None,
+                arch: "arm64".into(),
+                platform: Some(ApplePlatform::IOS(Environment::Simulator)),
+                min_os: Some("14.0".into()),
+                is_dylib: false,
+                install_name: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn universal_binary() {
+        let data = fat(&[object(X86_64, 1, (10, 12)), object(ARM64, 1, (11, 0))]);
+        let objects = read_objects(&data).unwrap();
+        let archs = objects.iter().map(|o| o.arch.as_str()).collect::<Vec<_>>();
+        assert_eq!(archs, ["x86_64", "arm64"]);
+        assert!(
+            objects
+                .iter()
+                .all(|o| o.platform == Some(ApplePlatform::MacOS))
+        );
+        assert_eq!(objects[0].min_os.as_deref(), Some("10.12"));
+    }
+
+    #[test]
+    fn static_library_members() {
+        let lib = archive(&[
+            ("mylib-1.o", object(ARM64, 2, (15, 0))),
+            ("odd.o", object(ARM64, 1, (11, 0))),
+            ("bitcode.o", b"BC\xc0\xde".to_vec()),
+        ]);
+        let objects = read_objects(&fat(&[lib])).unwrap();
+        let members = objects
+            .iter()
+            .map(|o| (o.member.as_deref().unwrap(), o.platform.clone().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            members,
+            [
+                ("mylib-1.o", ApplePlatform::IOS(Environment::Device)),
+                ("odd.o", ApplePlatform::MacOS)
+            ]
+        );
+    }
+
+    #[test]
+    fn dynamic_library() {
+        let objects = read_objects(&dylib(ARM64, 1, "@rpath/A.framework/Versions/A/A")).unwrap();
+        assert!(objects[0].is_dylib);
+        assert_eq!(
+            objects[0].install_name.as_deref(),
+            Some("@rpath/A.framework/Versions/A/A")
+        );
+        assert_eq!(objects[0].platform, Some(ApplePlatform::MacOS));
+    }
+
+    #[test]
+    fn objects_are_checked_against_the_slice() {
+        let simulator = ApplePlatform::IOS(Environment::Simulator);
+        let mut objects = read_objects(&archive(&[
+            ("ok.o", object(ARM64, 7, (14, 0))),
+            ("macos.o", object(ARM64, 1, (11, 0))),
+            ("newer.o", object(ARM64, 7, (17, 2))),
+        ]))
+        .unwrap();
+        objects.push(MachObject {
+            platform: None,
+            ..objects[0].clone()
+        });
+
+        let problems = objects
+            .iter()
+            .filter_map(|o| check_object(o, &simulator, Some("14.0")))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                "macos.o (arm64) is built for macOS instead of iOS Simulator",
+                "newer.o (arm64) requires iOS Simulator 17.2, newer than the deployment target 14.0"
+            ]
+        );
+        assert_eq!(check_object(&objects[2], &simulator, None), None);
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        use std::cmp::Ordering;
+        assert_eq!(compare_versions("10.12", "10.9"), Ordering::Greater);
+        assert_eq!(compare_versions("14", "14.0.0"), Ordering::Equal);
+        assert_eq!(compare_versions("13.1", "14.0"), Ordering::Less);
+    }
+
+    #[test]
+    fn not_a_binary() {
+        let err = read_objects(b"#!/bin/sh\n").unwrap_err().to_string();
+        assert!(err.contains("not a Mach-O file"), "{err}");
+    }
+}
@@ -0,0 +1,83 @@
+//! Parsing of Clang `module.modulemap` files.
+
+use anyhow::{Result, bail};
+
+/// The name of the top-level module declared in a modulemap.
+pub fn parse_module_name(content: &str) -> Result<String> {
+    let found_start = content.lines().find_map(|line| {
+        line.strip_prefix("framework module ")
+            .or_else(|| line.strip_prefix("module "))
+    });
+
+    let Some(found_start) = found_start else {
+        bail!("No 'module' declaration found");
+    };
+
+    let mut module = found_start.trim_end();
+    if module.ends_with('{') {
+        module = module[..module.len() - 1].trim_end();
+    } else {
+        bail!("Expected `module <name> {{` not `{module}`");
+    }
+    Ok(module.to_string())
+}
+
+/// Rename the top-level module declared in a modulemap.
+pub fn rename_module(content: &str, name: &str) -> Result<String> {
+    let current = parse_module_name(content)?;
+    let mut renamed = false;
+    let lines = content.split_inclusive('\n').map(|line| {
+        let declaration = line.starts_with("framework module ") || line.starts_with("module ");
+        if renamed || !declaration {
+            return line.to_string();
+        }
+        renamed = true;
+        line.replacen(&format!("module {current}"), &format!("module {name}"), 1)
+    });
+    Ok(lines.collect())
+}
+
+/// The headers and umbrella directories a modulemap references.
+pub fn referenced_headers(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = line.split("//").next()?.trim();
+            let (keywords, rest) = line.split_once('"')?;
+            let keywords = keywords.split_whitespace().collect::<Vec<_>>();
+            let references = matches!(keywords.last(), Some(&"header") | Some(&"umbrella"));
+            if !references || keywords.contains(&"exclude") {
+                return None;
+            }
+            Some(rest.split('"').next()?.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_name() {
+        let content = "framework module MyMath {\n    header \"mymath.h\"\n}\n";
+        assert_eq!(parse_module_name(content).unwrap(), "MyMath");
+        assert!(parse_module_name("module MyMath\n").is_err());
+    }
+
+    #[test]
+    fn renamed_module() {
+        let content =
+            "framework module MyMath {\n    header \"mymath.h\"\n    module MyMathExt {}\n}\n";
+        assert_eq!(
+            rename_module(content, "MyMathDynamic").unwrap(),
+            "framework module MyMathDynamic {\n    header \"mymath.h\"\n    module MyMathExt {}\n}\n"
+        );
+    }
+
+    #[test]
+    fn headers_and_umbrella_directories() {
+        let content = "framework module A {\n  umbrella header \"A.h\"\n  private header \"B.h\"\n  exclude header \"C.h\"\n  umbrella \"Sub\"\n  link \"z\"\n}";
+        assert_eq!(referenced_headers(content), ["A.h", "B.h", "Sub"]);
+    }
+}
@@ -75,6 +75,53 @@ impl ApplePlatform {
         }
     }
 
+    /// The `SupportedPlatform` and `SupportedPlatformVariant` of the platform
+    /// in an XCFramework's Info.plist.
+    pub fn supported_platform(&self) -> (&'static str, Option<&'static str>) {
+        match self {
+            ApplePlatform::MacOS => ("macos", None),
+            ApplePlatform::IOS(Environment::Device) => ("ios", None),
+            ApplePlatform::IOS(Environment::Simulator) => ("ios", Some("simulator")),
+            ApplePlatform::IOS(Environment::Catalyst) => ("ios", Some("maccatalyst")),
+            ApplePlatform::TvOS(EnvironmentWithoutCatalyst::Device) => ("tvos", None),
+            ApplePlatform::TvOS(EnvironmentWithoutCatalyst::Simulator) => {
+                ("tvos", Some("simulator"))
+            }
+            ApplePlatform::WatchOS(EnvironmentWithoutCatalyst::Device) => ("watchos", None),
+            ApplePlatform::WatchOS(EnvironmentWithoutCatalyst::Simulator) => {
+                ("watchos", Some("simulator"))
+            }
+        }
+    }
+
+    /// The inverse of [`ApplePlatform::supported_platform`].
+    pub fn from_supported_platform(platform: &str, variant: Option<&str>) -> Option<Self> {
+        Some(match (platform, variant) {
+            ("macos", None) => ApplePlatform::MacOS,
+            ("ios", None) => ApplePlatform::IOS(Environment::Device),
+            ("ios", Some("simulator")) => ApplePlatform::IOS(Environment::Simulator),
+            ("ios", Some("maccatalyst")) => ApplePlatform::IOS(Environment::Catalyst),
+            ("tvos", None) => ApplePlatform::TvOS(EnvironmentWithoutCatalyst::Device),
+            ("tvos", Some("simulator")) => {
+                ApplePlatform::TvOS(EnvironmentWithoutCatalyst::Simulator)
+            }
+            ("watchos", None) => ApplePlatform::WatchOS(EnvironmentWithoutCatalyst::Device),
+            ("watchos", Some("simulator")) => {
+                ApplePlatform::WatchOS(EnvironmentWithoutCatalyst::Simulator)
+            }
+            _ => return None,
+        })
+    }
+
+    /// Whether frameworks for this platform use the macOS bundle layout,
+    /// with resources in a separate `Resources` directory.
+    pub fn has_versioned_bundle(&self) -> bool {
+        matches!(
+            self,
+            ApplePlatform::MacOS | ApplePlatform::IOS(Environment::Catalyst)
+        )
+    }
+
     /// Returns the environment variable name for the deployment target of this platform.
     pub fn deployment_target_env_var(&self) -> &'static str {
         match self {
@@ -96,6 +143,22 @@ impl ApplePlatform {
         }
     }
 
+    /// The lowest deployment target rustc uses for an architecture of this
+    /// platform, for architectures introduced after the platform's default.
+    ///
+    /// Reference: [rustc_target](https://github.com/rust-lang/rust/blob/master/compiler/rustc_target/src/spec/base/apple/mod.rs)
+    pub fn minimum_deployment_target(&self, arch: &str) -> &'static str {
+        match (self, arch) {
+            (ApplePlatform::MacOS, "arm64") => "11.0",
+            (ApplePlatform::IOS(Environment::Simulator), "arm64") => "14.0",
+            (ApplePlatform::IOS(Environment::Catalyst), "arm64") => "14.0",
+            (ApplePlatform::IOS(Environment::Catalyst), _) => "13.1",
+            (ApplePlatform::TvOS(EnvironmentWithoutCatalyst::Simulator), "arm64") => "14.0",
+            (ApplePlatform::WatchOS(EnvironmentWithoutCatalyst::Simulator), "arm64") => "7.0",
+            _ => self.default_deployment_target(),
+        }
+    }
+
     // Reference: https://doc.rust-lang.org/rustc/platform-support.html
     pub fn rustup_targets(&self) -> Vec<&str> {
         match self {
@@ -1,10 +1,111 @@
+use std::fmt;
+
+use camino_fs::Utf8PathBuf;
+use plist::{Dictionary, Value};
+
 use super::platform::ApplePlatform;
 
+/// Identity and version metadata of a framework bundle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleInfo {
+    /// Defaults to `xcframework.cargo.<bundle name>`
+    pub identifier: Option<String>,
+    /// `CFBundleShortVersionString`
+    pub short_version: String,
+    /// `CFBundleVersion`
+    pub build_number: String,
+    /// Additional keys merged over the generated ones
+    pub custom_keys: Dictionary,
+    /// `MinimumOSVersion`, defaults to the deployment target of the
+    /// process environment
+    pub minimum_os_version: Option<String>,
+}
+
+impl Default for BundleInfo {
+    fn default() -> Self {
+        Self {
+            identifier: None,
+            short_version: "1.0".into(),
+            build_number: "1".into(),
+            custom_keys: Dictionary::new(),
+            minimum_os_version: None,
+        }
+    }
+}
+
+impl BundleInfo {
+    /// Derive the bundle versions from a Cargo package version.
+    ///
+    /// `CFBundleShortVersionString` only allows up to three period-separated
+    /// integers, so pre-release and build metadata are dropped. The build
+    /// number defaults to the short version.
+    pub fn from_package_version(version: &str, build_number: Option<String>) -> Self {
+        let short_version = version
+            .split(['-', '+'])
+            .next()
+            .unwrap_or(version)
+            .to_string();
+        Self {
+            identifier: None,
+            build_number: build_number.unwrap_or_else(|| short_version.clone()),
+            short_version,
+            custom_keys: Dictionary::new(),
+            minimum_os_version: None,
+        }
+    }
+}
+
 pub struct InfoPlistBuilder {
     bundle_name: String,
     platform: ApplePlatform,
     sdk_version: String,
     min_os_version: String,
+    info: BundleInfo,
+}
+
+/// The keys generated by [`InfoPlistBuilder`], which custom keys may not override.
+pub const GENERATED_KEYS: &[&str] = &[
+    "CFBundleExecutable",
+    "CFBundleIdentifier",
+    "CFBundleInfoDictionaryVersion",
+    "CFBundleName",
+    "CFBundlePackageType",
+    "CFBundleShortVersionString",
+    "CFBundleSupportedPlatforms",
+    "CFBundleVersion",
+    "DTPlatformName",
+    "DTSDKName",
+    "MinimumOSVersion",
+];
+
+/// Check that custom Info.plist keys don't override the generated ones.
+pub fn check_custom_keys(custom_keys: &Dictionary) -> anyhow::Result<()> {
+    let overridden = custom_keys
+        .keys()
+        .filter(|key| GENERATED_KEYS.contains(&key.as_str()))
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    if !overridden.is_empty() {
+        anyhow::bail!(
+            "The info-plist keys {} are generated and can't be overridden",
+            overridden.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Sort the keys of a dictionary and all nested dictionaries, so that the
+/// written plist doesn't depend on the order the keys were inserted in.
+pub fn sort_keys(dict: &mut Dictionary) {
+    fn sort_value(value: &mut Value) {
+        match value {
+            Value::Dictionary(dict) => sort_keys(dict),
+            Value::Array(items) => items.iter_mut().for_each(sort_value),
+            _ => {}
+        }
+    }
+    dict.sort_keys();
+    dict.values_mut().for_each(sort_value);
 }
 
 impl InfoPlistBuilder {
@@ -19,52 +120,73 @@ impl InfoPlistBuilder {
             platform,
             sdk_version,
             min_os_version,
+            info: BundleInfo::default(),
         }
     }
 
-    pub fn write(&self, path: &str) -> std::io::Result<()> {
-        let template = TEAMPLATE
-            .replace("{BUNDLE_NAME}", &self.bundle_name)
-            .replace("{SUPPORTED_PLATFORM}", self.platform.platform_name())
-            .replace("{PLATFORM_NAME}", self.platform.platform_name())
-            .replace("{SDK_VERSION}", &self.sdk_version)
-            .replace("{MIN_OS_VERSION}", &self.min_os_version);
-        std::fs::write(path, template)
+    pub fn bundle_info(mut self, info: BundleInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// The generated keys merged with the custom keys.
+    pub fn dictionary(&self) -> anyhow::Result<Dictionary> {
+        check_custom_keys(&self.info.custom_keys)?;
+
+        let identifier = self
+            .info
+            .identifier
+            .clone()
+            .unwrap_or_else(|| format!("xcframework.cargo.{}", self.bundle_name));
+        let platform_name = self.platform.platform_name();
+
+        let mut dict = Dictionary::new();
+        let mut insert = |key: &str, value: Value| dict.insert(key.into(), value);
+        insert("CFBundleExecutable", self.bundle_name.as_str().into());
+        insert("CFBundleIdentifier", identifier.into());
+        insert("CFBundleInfoDictionaryVersion", "6.0".into());
+        insert("CFBundleName", self.bundle_name.as_str().into());
+        insert("CFBundlePackageType", "FMWK".into());
+        insert(
+            "CFBundleShortVersionString",
+            self.info.short_version.as_str().into(),
+        );
+        insert(
+            "CFBundleSupportedPlatforms",
+            Value::Array(vec![platform_name.into()]),
+        );
+        insert("CFBundleVersion", self.info.build_number.as_str().into());
+        insert("DTPlatformName", platform_name.into());
+        insert(
+            "DTSDKName",
+            format!("{platform_name}{}", self.sdk_version).into(),
+        );
+        insert("MinimumOSVersion", self.min_os_version.as_str().into());
+
+        for (key, value) in &self.info.custom_keys {
+            dict.insert(key.clone(), value.clone());
+        }
+        sort_keys(&mut dict);
+        Ok(dict)
+    }
+
+    /// Write the Info.plist as XML.
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        Value::Dictionary(self.dictionary()?).to_file_xml(path)?;
+        Ok(())
     }
 }
 
-const TEAMPLATE: &str = r###"
-<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0">
-<dict>
-	<key>CFBundleExecutable</key>
-	<string>{BUNDLE_NAME}</string>
-	<key>CFBundleIdentifier</key>
-	<string>xcframework.cargo.{BUNDLE_NAME}</string>
-	<key>CFBundleInfoDictionaryVersion</key>
-	<string>6.0</string>
-	<key>CFBundleName</key>
-	<string>{BUNDLE_NAME}</string>
-	<key>CFBundlePackageType</key>
-	<string>APPL</string>
-	<key>CFBundleShortVersionString</key>
-	<string>1.0</string>
-	<key>CFBundleSupportedPlatforms</key>
-	<array>
-		<string>{SUPPORTED_PLATFORM}</string>
-	</array>
-	<key>CFBundleVersion</key>
-	<string>1</string>
-	<key>DTPlatformName</key>
-	<string>{PLATFORM_NAME}</string>
-	<key>DTSDKName</key>
-	<string>{PLATFORM_NAME}{SDK_VERSION}</string>
-	<key>MinimumOSVersion</key>
-	<string>{MIN_OS_VERSION}</string>
-</dict>
-</plist>
-"###;
+/// The context of the errors raised while writing the Info.plist at the
+/// path, to tell them apart from the other failures of a framework.
+#[derive(Debug, Clone)]
+pub struct WritingPlist(pub Utf8PathBuf);
+
+impl fmt::Display for WritingPlist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "writing {}", self.0)
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -166,6 +288,120 @@ mod tests {
         assert_eq!(min_os, "10.0", "MinimumOSVersion should still be set");
     }
 
+    #[test]
+    fn plist_bundle_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Info.plist");
+        let path_str = path.to_str().unwrap();
+
+        let write = |info: BundleInfo| {
+            InfoPlistBuilder::new("MyLib", ApplePlatform::MacOS, "15.0".into(), "11.0".into())
+                .bundle_info(info)
+                .write(path_str)
+                .unwrap();
+            std::fs::read_to_string(path_str).unwrap()
+        };
+
+        let contents = write(BundleInfo::default());
+        assert_eq!(
+            extract_plist_value(&contents, "CFBundlePackageType"),
+            "FMWK"
+        );
+        assert_eq!(
+            extract_plist_value(&contents, "CFBundleIdentifier"),
+            "xcframework.cargo.MyLib"
+        );
+
+        let contents = write(BundleInfo {
+            identifier: Some("com.example.mylib".into()),
+            ..BundleInfo::from_package_version("2.3.4-beta.1+abc", Some("42".into()))
+        });
+        assert_eq!(
+            extract_plist_value(&contents, "CFBundleIdentifier"),
+            "com.example.mylib"
+        );
+        assert_eq!(
+            extract_plist_value(&contents, "CFBundleShortVersionString"),
+            "2.3.4"
+        );
+        assert_eq!(extract_plist_value(&contents, "CFBundleVersion"), "42");
+    }
+
+    #[test]
+    fn custom_keys_are_merged() {
+        let custom_keys: Dictionary = serde_json::from_value(serde_json::json!({
+            "NSHumanReadableCopyright": "Copyright © Acme",
+            "MySDKVersion": 3,
+            "MyFlags": { "beta": true, "ratio": 0.5 },
+        }))
+        .unwrap();
+        let info = BundleInfo {
+            custom_keys,
+            ..Default::default()
+        };
+
+        let dict =
+            InfoPlistBuilder::new("MyLib", ApplePlatform::MacOS, "15.0".into(), "11.0".into())
+                .bundle_info(info)
+                .dictionary()
+                .unwrap();
+
+        assert_eq!(dict["CFBundleExecutable"], Value::from("MyLib"));
+        assert_eq!(
+            dict["NSHumanReadableCopyright"],
+            Value::from("Copyright © Acme")
+        );
+        assert_eq!(dict["MySDKVersion"], Value::Integer(3.into()));
+        let flags = dict["MyFlags"].as_dictionary().unwrap();
+        assert_eq!(flags["beta"], Value::Boolean(true));
+        assert_eq!(flags["ratio"], Value::Real(0.5));
+
+        let keys = dict.keys().cloned().collect::<Vec<_>>();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted, "keys should be in a stable order");
+    }
+
+    #[test]
+    fn custom_keys_cannot_override_generated_keys() {
+        let mut custom_keys = Dictionary::new();
+        custom_keys.insert("CFBundleExecutable".into(), "Other".into());
+        custom_keys.insert("MinimumOSVersion".into(), "9.0".into());
+
+        let err = check_custom_keys(&custom_keys).unwrap_err().to_string();
+        assert!(
+            err.contains("CFBundleExecutable, MinimumOSVersion"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn build_number_defaults_to_short_version() {
+        let info = BundleInfo::from_package_version("0.2.1", None);
+        assert_eq!(info.short_version, "0.2.1");
+        assert_eq!(info.build_number, "0.2.1");
+    }
+
+    #[test]
+    fn plist_failures_can_be_told_apart() {
+        use anyhow::Context;
+
+        let path = Utf8PathBuf::from("tests/temp/missing/Info.plist");
+        let plist = InfoPlistBuilder::new(
+            "MyMath",
+            ApplePlatform::MacOS,
+            "15.0".into(),
+            "10.12".into(),
+        );
+        let error = plist
+            .write(path.as_str())
+            .with_context(|| WritingPlist(path.clone()))
+            .context("wrapping MyMath.framework")
+            .unwrap_err();
+        let WritingPlist(failed) = error.downcast_ref().unwrap();
+        assert_eq!(*failed, path);
+    }
+
     fn extract_plist_value(plist: &str, key: &str) -> String {
         let key_tag = format!("<key>{key}</key>");
         let after_key = plist.split(&key_tag).nth(1).unwrap();
@@ -0,0 +1,284 @@
+//! Privacy manifest (`PrivacyInfo.xcprivacy`) support.
+//!
+//! Reference: [Privacy manifest files](https://developer.apple.com/documentation/bundleresources/privacy_manifest_files)
+
+use anyhow::{Context, Result, bail};
+use camino_fs::*;
+use plist::{Dictionary, Value};
+use serde::Deserialize;
+
+pub const FILE_NAME: &str = "PrivacyInfo.xcprivacy";
+
+/// The `privacy-manifest` key: either a path to an `.xcprivacy` file or an
+/// inline table with the manifest content.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum PrivacyManifest {
+    File(Utf8PathBuf),
+    Inline(Dictionary),
+}
+
+/// The required-reason API categories and their approved reason codes.
+///
+/// Reference: [Describing use of required reason API](https://developer.apple.com/documentation/bundleresources/privacy_manifest_files/describing_use_of_required_reason_api)
+const API_CATEGORIES: &[(&str, &[&str])] = &[
+    (
+        "NSPrivacyAccessedAPICategoryFileTimestamp",
+        &["DDA9.1", "C617.1", "3B52.1", "0A2A.1"],
+    ),
+    (
+        "NSPrivacyAccessedAPICategorySystemBootTime",
+        &["35F9.1", "8FFB.1", "3D61.1"],
+    ),
+    (
+        "NSPrivacyAccessedAPICategoryDiskSpace",
+        &["85F4.1", "E174.1", "7D9E.1", "B728.1"],
+    ),
+    (
+        "NSPrivacyAccessedAPICategoryActiveKeyboards",
+        &["3EC4.1", "54BD.1"],
+    ),
+    (
+        "NSPrivacyAccessedAPICategoryUserDefaults",
+        &["CA92.1", "1C8F.1", "C56D.1", "AC6B.1"],
+    ),
+];
+
+impl PrivacyManifest {
+    /// Resolve a relative manifest path against the package directory.
+    pub fn relative_to(self, package_dir: &Utf8Path) -> Self {
+        match self {
+            Self::File(path) => Self::File(package_dir.join(path)),
+            inline => inline,
+        }
+    }
+
+    /// Load and validate the manifest content.
+    pub fn load(&self) -> Result<Dictionary> {
+        let dict = match self {
+            Self::File(path) => Value::from_file(path)
+                .with_context(|| format!("reading privacy manifest {path}"))?
+                .into_dictionary()
+                .with_context(|| format!("the privacy manifest {path} is not a dictionary"))?,
+            Self::Inline(dict) => dict.clone(),
+        };
+        validate(&dict)?;
+        Ok(dict)
+    }
+
+    /// Validate the manifest and write it as an XML plist.
+    pub fn write(&self, path: &Utf8Path) -> Result<()> {
+        let mut dict = self.load()?;
+        super::plist::sort_keys(&mut dict);
+        if let Some(parent) = path.parent() {
+            parent.mkdirs()?;
+        }
+        Value::Dictionary(dict).to_file_xml(path)?;
+        Ok(())
+    }
+}
+
+/// Check the manifest against the known keys and required-reason API categories.
+pub fn validate(manifest: &Dictionary) -> Result<()> {
+    let mut problems = Vec::new();
+
+    for (key, value) in manifest {
+        match key.as_str() {
+            "NSPrivacyTracking" => {
+                if value.as_boolean().is_none() {
+                    problems.push(format!("{key} must be a boolean"));
+                }
+            }
+            "NSPrivacyTrackingDomains" => {
+                if !is_string_array(value) {
+                    problems.push(format!("{key} must be an array of strings"));
+                }
+            }
+            "NSPrivacyCollectedDataTypes" => {
+                check_dict_array(key, value, &mut problems, check_collected_data_type)
+            }
+            "NSPrivacyAccessedAPITypes" => {
+                check_dict_array(key, value, &mut problems, check_accessed_api_type)
+            }
+            _ => problems.push(format!("unknown key {key}")),
+        }
+    }
+
+    let has_domains = manifest
+        .get("NSPrivacyTrackingDomains")
+        .and_then(Value::as_array)
+        .is_some_and(|domains| !domains.is_empty());
+    let tracking = manifest
+        .get("NSPrivacyTracking")
+        .and_then(Value::as_boolean)
+        .unwrap_or(false);
+    if has_domains && !tracking {
+        problems.push("NSPrivacyTrackingDomains requires NSPrivacyTracking to be true".into());
+    }
+
+    if !problems.is_empty() {
+        bail!("Invalid privacy manifest:\n  {}", problems.join("\n  "));
+    }
+    Ok(())
+}
+
+fn check_dict_array(
+    key: &str,
+    value: &Value,
+    problems: &mut Vec<String>,
+    check: fn(&str, &Dictionary, &mut Vec<String>),
+) {
+    let Some(items) = value.as_array() else {
+        problems.push(format!("{key} must be an array of dictionaries"));
+        return;
+    };
+    for (i, item) in items.iter().enumerate() {
+        match item.as_dictionary() {
+            Some(dict) => check(&format!("{key}[{i}]"), dict, problems),
+            None => problems.push(format!("{key}[{i}] must be a dictionary")),
+        }
+    }
+}
+
+fn check_collected_data_type(at: &str, dict: &Dictionary, problems: &mut Vec<String>) {
+    for (key, value) in dict {
+        let valid = match key.as_str() {
+            "NSPrivacyCollectedDataType" => value.as_string().is_some(),
+            "NSPrivacyCollectedDataTypeLinked" | "NSPrivacyCollectedDataTypeTracking" => {
+                value.as_boolean().is_some()
+            }
+            "NSPrivacyCollectedDataTypePurposes" => is_string_array(value),
+            _ => {
+                problems.push(format!("{at}: unknown key {key}"));
+                continue;
+            }
+        };
+        if !valid {
+            problems.push(format!("{at}: {key} has the wrong type"));
+        }
+    }
+    for required in [
+        "NSPrivacyCollectedDataType",
+        "NSPrivacyCollectedDataTypeLinked",
+        "NSPrivacyCollectedDataTypeTracking",
+        "NSPrivacyCollectedDataTypePurposes",
+    ] {
+        if !dict.contains_key(required) {
+            problems.push(format!("{at}: missing {required}"));
+        }
+    }
+}
+
+fn check_accessed_api_type(at: &str, dict: &Dictionary, problems: &mut Vec<String>) {
+    for key in dict.keys() {
+        if key != "NSPrivacyAccessedAPIType" && key != "NSPrivacyAccessedAPITypeReasons" {
+            problems.push(format!("{at}: unknown key {key}"));
+        }
+    }
+
+    let Some(category) = dict
+        .get("NSPrivacyAccessedAPIType")
+        .and_then(Value::as_string)
+    else {
+        problems.push(format!("{at}: missing NSPrivacyAccessedAPIType"));
+        return;
+    };
+    let Some((_, known_reasons)) = API_CATEGORIES.iter().find(|(name, _)| *name == category) else {
+        problems.push(format!("{at}: unknown API category {category}"));
+        return;
+    };
+
+    let reasons = dict
+        .get("NSPrivacyAccessedAPITypeReasons")
+        .and_then(Value::as_array);
+    match reasons {
+        Some(reasons) if !reasons.is_empty() => {
+            for reason in reasons {
+                match reason.as_string() {
+                    Some(reason) if known_reasons.contains(&reason) => {}
+                    Some(reason) => {
+                        problems.push(format!("{at}: reason {reason} is not valid for {category}"))
+                    }
+                    None => problems.push(format!("{at}: reasons must be strings")),
+                }
+            }
+        }
+        _ => problems.push(format!(
+            "{at}: NSPrivacyAccessedAPITypeReasons must list at least one reason"
+        )),
+    }
+}
+
+fn is_string_array(value: &Value) -> bool {
+    value
+        .as_array()
+        .is_some_and(|items| items.iter().all(|item| item.as_string().is_some()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: serde_json::Value) -> PrivacyManifest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn inline_table_is_written_as_plist() {
+        let manifest = parse(serde_json::json!({
+            "NSPrivacyTracking": false,
+            "NSPrivacyAccessedAPITypes": [{
+                "NSPrivacyAccessedAPIType": "NSPrivacyAccessedAPICategoryUserDefaults",
+                "NSPrivacyAccessedAPITypeReasons": ["CA92.1"],
+            }],
+        }));
+        assert!(matches!(manifest, PrivacyManifest::Inline(_)));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.path().join(FILE_NAME)).unwrap();
+        manifest.write(&path).unwrap();
+
+        let written = PrivacyManifest::File(path).load().unwrap();
+        assert_eq!(
+            written.get("NSPrivacyTracking"),
+            Some(&Value::Boolean(false))
+        );
+    }
+
+    #[test]
+    fn path_is_resolved_against_package_dir() {
+        let manifest = parse(serde_json::json!("PrivacyInfo.xcprivacy"));
+        assert_eq!(
+            manifest.relative_to(Utf8Path::new("/pkg")),
+            PrivacyManifest::File("/pkg/PrivacyInfo.xcprivacy".into())
+        );
+    }
+
+    #[test]
+    fn invalid_manifest_lists_all_problems() {
+        let manifest = parse(serde_json::json!({
+            "NSPrivacyTracking": "yes",
+            "NSPrivacyTrackingDomains": ["example.com"],
+            "NSPrivacyUnknown": 1,
+            "NSPrivacyAccessedAPITypes": [
+                {
+                    "NSPrivacyAccessedAPIType": "NSPrivacyAccessedAPICategoryDiskSpace",
+                    "NSPrivacyAccessedAPITypeReasons": ["CA92.1"],
+                },
+                { "NSPrivacyAccessedAPIType": "NSPrivacyAccessedAPICategoryLocation" },
+            ],
+            "NSPrivacyCollectedDataTypes": [{ "NSPrivacyCollectedDataType": "Email" }],
+        }));
+        let err = manifest.load().unwrap_err().to_string();
+        for expected in [
+            "NSPrivacyTracking must be a boolean",
+            "unknown key NSPrivacyUnknown",
+            "NSPrivacyTrackingDomains requires NSPrivacyTracking",
+            "reason CA92.1 is not valid for NSPrivacyAccessedAPICategoryDiskSpace",
+            "unknown API category NSPrivacyAccessedAPICategoryLocation",
+            "NSPrivacyCollectedDataTypes[0]: missing NSPrivacyCollectedDataTypeLinked",
+        ] {
+            assert!(err.contains(expected), "missing '{expected}' in:\n{err}");
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Helpers for bit-for-bit reproducible output.
+//!
+//! Reference: [SOURCE_DATE_EPOCH](https://reproducible-builds.org/docs/source-date-epoch/)
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use camino_fs::*;
+
+/// The timestamp to use for archive entries, in seconds since the unix epoch.
+///
+/// Read from `SOURCE_DATE_EPOCH`, falling back to 1980-01-01, the earliest
+/// date a zip archive can represent.
+pub fn source_date_epoch() -> u64 {
+    const ZIP_EPOCH: u64 = 315_532_800;
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(ZIP_EPOCH)
+}
+
+/// Convert seconds since the unix epoch to a UTC
+/// (year, month, day, hour, minute, second).
+pub fn utc_date_time(secs: u64) -> (u16, u8, u8, u8, u8, u8) {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Howard Hinnant's civil_from_days algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (
+        year as u16,
+        month as u8,
+        day as u8,
+        (rem / 3600) as u8,
+        (rem % 3600 / 60) as u8,
+        (rem % 60) as u8,
+    )
+}
+
+/// The permissions stored for an entry: 0755 for directories and
+/// executables, 0644 for everything else.
+pub fn normalized_mode(is_dir: bool, mode: u32) -> u32 {
+    if is_dir || mode & 0o111 != 0 {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+/// Compare two build outputs, either directories or archives, and list the
+/// differences.
+pub fn diff_outputs(a: &Utf8Path, b: &Utf8Path) -> Result<Vec<String>> {
+    if a.is_dir() && b.is_dir() {
+        return Ok(diff_maps(&tree_contents(a)?, &tree_contents(b)?));
+    }
+    let (bytes_a, bytes_b) = (a.read_bytes()?, b.read_bytes()?);
+    if bytes_a == bytes_b {
+        return Ok(vec![]);
+    }
+    let mut diffs = match (zip_contents(a), zip_contents(b)) {
+        (Ok(a), Ok(b)) => diff_maps(&a, &b),
+        _ => vec![],
+    };
+    if diffs.is_empty() {
+        diffs.push(format!("{} and {} differ", a, b));
+    }
+    Ok(diffs)
+}
+
+fn diff_maps(a: &BTreeMap<String, Vec<u8>>, b: &BTreeMap<String, Vec<u8>>) -> Vec<String> {
+    let mut diffs = vec![];
+    for (name, content) in a {
+        match b.get(name) {
+            None => diffs.push(format!("{name}: only in the first build")),
+            Some(other) if other != content => diffs.push(format!("{name}: content differs")),
+            Some(_) => {}
+        }
+    }
+    for name in b.keys().filter(|name| !a.contains_key(*name)) {
+        diffs.push(format!("{name}: only in the second build"));
+    }
+    diffs
+}
+
+/// The files and symlinks below `root` with their content or link target.
+fn tree_contents(root: &Utf8Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut contents = BTreeMap::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in dir.read_dir_utf8()? {
+            let path = entry?.into_path();
+            let name = path.strip_prefix(root)?.to_string();
+            let file_type = path.symlink_metadata()?.file_type();
+            if file_type.is_symlink() {
+                let target = path.read_link_utf8()?;
+                contents.insert(name, format!("-> {target}").into_bytes());
+            } else if file_type.is_dir() {
+                pending.push(path);
+            } else {
+                contents.insert(name, path.read_bytes()?);
+            }
+        }
+    }
+    Ok(contents)
+}
+
+/// The entries of a zip archive with their content and metadata.
+fn zip_contents(path: &Utf8Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)
+        .with_context(|| format!("reading zip archive {path}"))?;
+    let mut contents = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let mut content = format!(
+            "mode {:o} modified {}\n",
+            entry.unix_mode().unwrap_or_default(),
+            entry.last_modified().unwrap_or_default()
+        )
+        .into_bytes();
+        entry.read_to_end(&mut content)?;
+        contents.insert(entry.name().to_string(), content);
+    }
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utc_date_time_conversion() {
+        assert_eq!(utc_date_time(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(utc_date_time(315_532_800), (1980, 1, 1, 0, 0, 0));
+        assert_eq!(utc_date_time(1_709_251_199), (2024, 2, 29, 23, 59, 59));
+    }
+
+    #[test]
+    fn modes_are_normalized() {
+        assert_eq!(normalized_mode(true, 0o700), 0o755);
+        assert_eq!(normalized_mode(false, 0o700), 0o755);
+        assert_eq!(normalized_mode(false, 0o600), 0o644);
+        assert_eq!(normalized_mode(false, 0o666), 0o644);
+    }
+
+    #[test]
+    fn directory_differences_are_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        for root in [&a, &b] {
+            root.join("Info.plist").write("same").unwrap();
+        }
+        a.join("ios/A.framework/A").write("one").unwrap();
+        b.join("ios/A.framework/A").write("two").unwrap();
+        b.join("extra").write("").unwrap();
+
+        assert_eq!(
+            diff_outputs(&a, &b).unwrap(),
+            [
+                "ios/A.framework/A: content differs",
+                "extra: only in the second build"
+            ]
+        );
+        assert!(diff_outputs(&a, &a).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,265 @@
+//! Code signing of the wrapped frameworks and the assembled XCFramework.
+//!
+//! By default `codesign` is used, but any signing tool can be plugged in
+//! with a command template. The command is executed through the
+//! [`CommandRunner`] trait so that the signing step can be exercised without
+//! the Apple toolchain.
+
+use std::process::Command;
+
+use anyhow::{Result, bail};
+use camino_fs::*;
+use serde::Deserialize;
+
+/// The `[package.metadata.xcframework.signing]` section.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct SigningConfig {
+    /// The signing identity, e.g. "Apple Distribution: Company (TEAMID)" or
+    /// the SHA-1 hash of the certificate.
+    pub identity: Option<String>,
+
+    /// The keychain to look up the identity in.
+    pub keychain: Option<String>,
+
+    /// Whether to request a secure timestamp from Apple's timestamp server.
+    #[serde(default)]
+    pub timestamp: bool,
+
+    /// A command template replacing the default `codesign` invocation.
+    ///
+    /// The placeholders `{path}`, `{identity}` and `{keychain}` are replaced
+    /// before running the command.
+    pub command: Option<String>,
+}
+
+/// Runs the external commands needed by a build step.
+pub trait CommandRunner {
+    fn run(&self, program: &str, args: &[String]) -> Result<()>;
+}
+
+/// Runs commands as child processes.
+pub struct ProcessRunner;
+
+impl CommandRunner for ProcessRunner {
+    fn run(&self, program: &str, args: &[String]) -> Result<()> {
+        let output = Command::new(program).args(args).output()?;
+        if !output.status.success() {
+            bail!(
+                "{program} failed with status {:?}: {}",
+                output.status.code(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+impl SigningConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.identity.is_none() && self.command.is_none() {
+            bail!("The signing section requires either 'identity' or 'command'");
+        }
+        Ok(())
+    }
+
+    /// The program and arguments used to sign the bundle at `path`.
+    pub fn command_line(&self, path: &Utf8Path) -> Result<(String, Vec<String>)> {
+        if let Some(template) = &self.command {
+            return self.expand_template(template, path);
+        }
+        let Some(identity) = &self.identity else {
+            bail!("No signing identity configured");
+        };
+
+        let mut args = vec!["--force".to_string(), "--sign".into(), identity.clone()];
+        if let Some(keychain) = &self.keychain {
+            args.push("--keychain".into());
+            args.push(keychain.clone());
+        }
+        if self.timestamp {
+            args.push("--timestamp".into());
+        } else {
+            args.push("--timestamp=none".into());
+        }
+        args.push(path.to_string());
+        Ok(("codesign".into(), args))
+    }
+
+    fn expand_template(&self, template: &str, path: &Utf8Path) -> Result<(String, Vec<String>)> {
+        let mut words = Vec::new();
+        for word in split_command(template)? {
+            let mut word = word.replace("{path}", path.as_str());
+            for (placeholder, value) in [
+                ("{identity}", &self.identity),
+                ("{keychain}", &self.keychain),
+            ] {
+                if word.contains(placeholder) {
+                    let Some(value) = value else {
+                        bail!("The signing command uses {placeholder} but it is not configured");
+                    };
+                    word = word.replace(placeholder, value);
+                }
+            }
+            words.push(word);
+        }
+        if words.is_empty() {
+            bail!("The signing command is empty");
+        }
+        let program = words.remove(0);
+        Ok((program, words))
+    }
+}
+
+/// Sign a `.framework` or `.xcframework` bundle.
+pub fn sign_bundle(
+    config: &SigningConfig,
+    runner: &dyn CommandRunner,
+    path: &Utf8Path,
+) -> Result<()> {
+    let (program, args) = config.command_line(path)?;
+    runner.run(&program, &args)
+}
+
+/// Split a command template into words, honoring single and double quotes.
+fn split_command(template: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+
+    for c in template.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        bail!("Unterminated quote in signing command: {template}");
+    }
+    if in_word {
+        words.push(current);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct RecordingRunner {
+        calls: RefCell<Vec<(String, Vec<String>)>>,
+    }
+
+    impl CommandRunner for RecordingRunner {
+        fn run(&self, program: &str, args: &[String]) -> Result<()> {
+            self.calls
+                .borrow_mut()
+                .push((program.to_string(), args.to_vec()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn default_command_uses_codesign() {
+        let config = SigningConfig {
+            identity: Some("Apple Distribution: Acme (TEAMID)".into()),
+            keychain: Some("build.keychain".into()),
+            timestamp: true,
+            command: None,
+        };
+        let runner = RecordingRunner::default();
+        sign_bundle(&config, &runner, Utf8Path::new("out/MyLib.xcframework")).unwrap();
+
+        let calls = runner.calls.into_inner();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, "codesign");
+        assert_eq!(
+            calls[0].1,
+            [
+                "--force",
+                "--sign",
+                "Apple Distribution: Acme (TEAMID)",
+                "--keychain",
+                "build.keychain",
+                "--timestamp",
+                "out/MyLib.xcframework",
+            ]
+        );
+    }
+
+    #[test]
+    fn template_placeholders_are_expanded() {
+        let config = SigningConfig {
+            identity: Some("Acme Inc".into()),
+            command: Some(r#"my-signer --id "{identity}" --in '{path}'"#.into()),
+            ..Default::default()
+        };
+        let (program, args) = config
+            .command_line(Utf8Path::new("dir with space/A.framework"))
+            .unwrap();
+        assert_eq!(program, "my-signer");
+        assert_eq!(
+            args,
+            ["--id", "Acme Inc", "--in", "dir with space/A.framework"]
+        );
+    }
+
+    #[test]
+    fn template_with_unset_placeholder_fails() {
+        let config = SigningConfig {
+            command: Some("my-signer --keychain {keychain} {path}".into()),
+            ..Default::default()
+        };
+        assert!(config.command_line(Utf8Path::new("A.framework")).is_err());
+    }
+
+    #[test]
+    fn identity_or_command_is_required() {
+        assert!(SigningConfig::default().validate().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stand_in_signer_script_is_run() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let log = dir.join("signed.log");
+        let script = dir.join("fake-sign.sh");
+        script
+            .write(format!("#!/bin/sh\necho \"$@\" >> {log}\n"))
+            .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let config = SigningConfig {
+            identity: Some("ABCDEF".into()),
+            command: Some(format!("{script} {{identity}} {{path}}")),
+            ..Default::default()
+        };
+        sign_bundle(&config, &ProcessRunner, Utf8Path::new("A.framework")).unwrap();
+        sign_bundle(&config, &ProcessRunner, Utf8Path::new("A.xcframework")).unwrap();
+
+        assert_eq!(
+            log.read_string().unwrap(),
+            "ABCDEF A.framework\nABCDEF A.xcframework\n"
+        );
+    }
+}
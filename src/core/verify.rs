@@ -0,0 +1,352 @@
+//! Consistency checks for an existing XCFramework, without Xcode.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{Context, Result};
+use camino_fs::*;
+use plist::{Dictionary, Value};
+
+use super::macho;
+use super::modulemap::referenced_headers;
+use super::platform::ApplePlatform;
+
+/// Check an XCFramework and return the problems found.
+///
+/// Each library listed in the top-level Info.plist is checked against its
+/// binary: the `LibraryIdentifier` must match the supported platform and
+/// architectures, which must match the Mach-O headers. The framework's
+/// `CFBundleExecutable` and the headers referenced by the modulemap must exist,
+/// and all libraries must expose the same headers.
+pub fn verify_xcframework(path: &Utf8Path) -> Result<Vec<String>> {
+    let info_path = path.join("Info.plist");
+    let info = Value::from_file(&info_path).with_context(|| format!("reading {info_path}"))?;
+    let libraries = info
+        .as_dictionary()
+        .and_then(|info| info.get("AvailableLibraries"))
+        .and_then(Value::as_array)
+        .with_context(|| format!("{info_path} has no AvailableLibraries"))?;
+
+    let mut problems = vec![];
+    let mut slices = vec![];
+    for library in libraries {
+        let Some(library) = library.as_dictionary() else {
+            problems.push("AvailableLibraries contains a non-dictionary entry".into());
+            continue;
+        };
+        let Some(identifier) = string(library, "LibraryIdentifier") else {
+            problems.push("A library has no LibraryIdentifier".into());
+            continue;
+        };
+        let mut slice_problems = vec![];
+        let headers = verify_library(path, identifier, library, &mut slice_problems);
+        problems.extend(
+            slice_problems
+                .into_iter()
+                .map(|p| format!("{identifier}: {p}")),
+        );
+        if let Some(headers) = headers {
+            slices.push((identifier, headers));
+        }
+    }
+    if libraries.is_empty() {
+        problems.push("AvailableLibraries is empty".into());
+    }
+
+    if let Some(((reference, reference_headers), others)) = slices.split_first() {
+        for (identifier, headers) in others {
+            for (name, content) in headers {
+                match reference_headers.get(name) {
+                    None => {
+                        problems.push(format!("{identifier}: header {name} is not in {reference}"))
+                    }
+                    Some(other) if other != content => problems.push(format!(
+                        "{identifier}: header {name} differs from {reference}"
+                    )),
+                    Some(_) => {}
+                }
+            }
+            for name in reference_headers
+                .keys()
+                .filter(|n| !headers.contains_key(*n))
+            {
+                problems.push(format!(
+                    "{identifier}: header {name} of {reference} is missing"
+                ));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// Check one library and return its headers, keyed by their relative path.
+fn verify_library(
+    root: &Utf8Path,
+    identifier: &str,
+    library: &Dictionary,
+    problems: &mut Vec<String>,
+) -> Option<BTreeMap<String, Vec<u8>>> {
+    let mut archs = library
+        .get("SupportedArchitectures")
+        .and_then(Value::as_array)
+        .map(|archs| {
+            archs
+                .iter()
+                .filter_map(Value::as_string)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    archs.sort();
+    let supported_platform = string(library, "SupportedPlatform").unwrap_or_default();
+    let variant = string(library, "SupportedPlatformVariant");
+
+    let expected_identifier = format!(
+        "{supported_platform}-{}{}",
+        archs.join("_"),
+        variant.map(|v| format!("-{v}")).unwrap_or_default()
+    );
+    if identifier != expected_identifier {
+        problems.push(format!(
+            "LibraryIdentifier doesn't match the supported platform and architectures, expected {expected_identifier}"
+        ));
+    }
+    let platform = ApplePlatform::from_supported_platform(supported_platform, variant);
+    if platform.is_none() {
+        problems.push(format!(
+            "unknown platform {supported_platform}{}",
+            variant.map(|v| format!(" ({v})")).unwrap_or_default()
+        ));
+    }
+
+    let dir = root.join(identifier);
+    if !dir.is_dir() {
+        problems.push(format!("directory {dir} is missing"));
+        return None;
+    }
+    let Some(library_path) = string(library, "LibraryPath") else {
+        problems.push("LibraryPath is missing".into());
+        return None;
+    };
+
+    let (binary, headers_dir, modulemap) = if library_path.ends_with(".framework") {
+        let framework = dir.join(library_path);
+        let info_path = ["Resources/Info.plist", "Info.plist"]
+            .iter()
+            .map(|p| framework.join(p))
+            .find(|p| p.is_file());
+        let Some(info_path) = info_path else {
+            problems.push(format!("{library_path} has no Info.plist"));
+            return None;
+        };
+        let executable = Value::from_file(&info_path).ok().and_then(|info| {
+            info.as_dictionary()
+                .and_then(|info| string(info, "CFBundleExecutable"))
+                .map(str::to_string)
+        });
+        let Some(executable) = executable else {
+            problems.push(format!("{info_path} has no CFBundleExecutable"));
+            return None;
+        };
+        (
+            framework.join(executable),
+            Some(framework.join("Headers")),
+            framework.join("Modules/module.modulemap"),
+        )
+    } else {
+        let headers_dir = string(library, "HeadersPath").map(|p| dir.join(p));
+        let modulemap = headers_dir
+            .as_ref()
+            .map(|dir| dir.join("module.modulemap"))
+            .unwrap_or_default();
+        (dir.join(library_path), headers_dir, modulemap)
+    };
+
+    if binary.is_file() {
+        match macho::read_file(&binary) {
+            Ok(objects) => verify_binary(&objects, &archs, platform.as_ref(), problems),
+            Err(e) => problems.push(format!("{e:#}")),
+        }
+    } else {
+        problems.push(format!("binary {binary} is missing"));
+    }
+
+    let headers_dir = headers_dir.filter(|dir| dir.is_dir());
+    if modulemap.is_file() {
+        let content = modulemap.read_string().unwrap_or_default();
+        for header in referenced_headers(&content) {
+            let exists = headers_dir
+                .as_ref()
+                .is_some_and(|dir| dir.join(&header).exists());
+            if !exists {
+                problems.push(format!(
+                    "the modulemap references the missing header {header}"
+                ));
+            }
+        }
+    }
+
+    let mut headers = BTreeMap::new();
+    if let Some(headers_dir) = headers_dir {
+        for path in headers_dir.ls().recurse().files() {
+            let Ok(name) = path.strip_prefix(&headers_dir) else {
+                continue;
+            };
+            headers.insert(name.to_string(), path.read_bytes().unwrap_or_default());
+        }
+    }
+    Some(headers)
+}
+
+fn verify_binary(
+    objects: &[macho::MachObject],
+    archs: &[&str],
+    platform: Option<&ApplePlatform>,
+    problems: &mut Vec<String>,
+) {
+    if objects.is_empty() {
+        problems.push("the binary contains no Mach-O objects".into());
+        return;
+    }
+    let actual = objects
+        .iter()
+        .map(|o| o.arch.as_str())
+        .collect::<BTreeSet<_>>();
+    let supported = archs.iter().copied().collect::<BTreeSet<_>>();
+    if actual != supported {
+        problems.push(format!(
+            "the binary has the architectures {}, but SupportedArchitectures lists {}",
+            actual.into_iter().collect::<Vec<_>>().join(", "),
+            supported.into_iter().collect::<Vec<_>>().join(", ")
+        ));
+    }
+
+    let Some(platform) = platform else {
+        return;
+    };
+    problems.extend(
+        objects
+            .iter()
+            .filter_map(|object| macho::check_object(object, platform, None)),
+    );
+}
+
+fn string<'a>(dict: &'a Dictionary, key: &str) -> Option<&'a str> {
+    dict.get(key).and_then(Value::as_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::macho::testing::*;
+
+    fn library(identifier: &str, archs: &[&str], platform: &str, variant: Option<&str>) -> Value {
+        let mut dict = Dictionary::new();
+        dict.insert("LibraryIdentifier".into(), identifier.into());
+        dict.insert("LibraryPath".into(), "MyMath.framework".into());
+        dict.insert(
+            "SupportedArchitectures".into(),
+            Value::Array(archs.iter().map(|a| (*a).into()).collect()),
+        );
+        dict.insert("SupportedPlatform".into(), platform.into());
+        if let Some(variant) = variant {
+            dict.insert("SupportedPlatformVariant".into(), variant.into());
+        }
+        Value::Dictionary(dict)
+    }
+
+    fn framework(root: &Utf8Path, identifier: &str, binary: Vec<u8>, header: &str) {
+        let framework = root.join(identifier).join("MyMath.framework");
+        framework.join("MyMath").write(binary).unwrap();
+        framework.join("Headers/mymath.h").write(header).unwrap();
+        framework
+            .join("Modules/module.modulemap")
+            .write("framework module MyMath {\n    umbrella header \"mymath.h\"\n    header \"extra.h\" // optional\n    exclude header \"private.h\"\n    export *\n}\n")
+            .unwrap();
+        framework.join("Headers/extra.h").write("").unwrap();
+        let mut info = Dictionary::new();
+        info.insert("CFBundleExecutable".into(), "MyMath".into());
+        Value::Dictionary(info)
+            .to_file_xml(framework.join("Info.plist"))
+            .unwrap();
+    }
+
+    fn xcframework(root: &Utf8Path, libraries: Vec<Value>) {
+        let mut info = Dictionary::new();
+        info.insert("AvailableLibraries".into(), Value::Array(libraries));
+        Value::Dictionary(info)
+            .to_file_xml(root.join("Info.plist"))
+            .unwrap();
+    }
+
+    fn temp_dir() -> (tempfile::TempDir, Utf8PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.path().join("MyMath.xcframework")).unwrap();
+        path.mkdirs().unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn valid_xcframework() {
+        let (_dir, root) = temp_dir();
+        framework(&root, "ios-arm64", object(ARM64, 2, (15, 0)), "int add();");
+        let simulator = fat(&[object(ARM64, 7, (15, 0)), object(X86_64, 7, (15, 0))]);
+        framework(&root, "ios-arm64_x86_64-simulator", simulator, "int add();");
+        xcframework(
+            &root,
+            vec![
+                library("ios-arm64", &["arm64"], "ios", None),
+                library(
+                    "ios-arm64_x86_64-simulator",
+                    &["x86_64", "arm64"],
+                    "ios",
+                    Some("simulator"),
+                ),
+            ],
+        );
+
+        assert_eq!(verify_xcframework(&root).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn problems_are_listed() {
+        let (_dir, root) = temp_dir();
+        framework(&root, "ios-arm64", object(ARM64, 7, (15, 0)), "int add();");
+        let macos = fat(&[object(ARM64, 1, (11, 0))]);
+        framework(&root, "macos-arm64_x86_64", macos, "int sub();");
+        let macos_framework = root.join("macos-arm64_x86_64/MyMath.framework");
+        macos_framework.join("Headers/extra.h").rm().unwrap();
+        xcframework(
+            &root,
+            vec![
+                library("ios-arm64", &["arm64"], "ios", None),
+                library("macos-arm64_x86_64", &["arm64", "x86_64"], "macos", None),
+                library("ios-arm64-simulator", &["arm64"], "ios", None),
+            ],
+        );
+
+        let problems = verify_xcframework(&root).unwrap();
+        assert_eq!(
+            problems,
+            [
+                "ios-arm64: the binary (arm64) is built for iOS Simulator instead of iOS",
+                "macos-arm64_x86_64: the binary has the architectures arm64, but SupportedArchitectures lists arm64, x86_64",
+                "macos-arm64_x86_64: the modulemap references the missing header extra.h",
+                "ios-arm64-simulator: LibraryIdentifier doesn't match the supported platform and architectures, expected ios-arm64",
+                &format!("ios-arm64-simulator: directory {root}/ios-arm64-simulator is missing"),
+                "macos-arm64_x86_64: header mymath.h differs from ios-arm64",
+                "macos-arm64_x86_64: header extra.h of ios-arm64 is missing",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_executable() {
+        let (_dir, root) = temp_dir();
+        framework(&root, "ios-arm64", object(ARM64, 2, (15, 0)), "");
+        root.join("ios-arm64/MyMath.framework/MyMath").rm().unwrap();
+        xcframework(&root, vec![library("ios-arm64", &["arm64"], "ios", None)]);
+
+        let problems = verify_xcframework(&root).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("MyMath.framework/MyMath is missing"));
+    }
+}
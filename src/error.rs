@@ -0,0 +1,219 @@
+use std::fmt;
+
+use camino_fs::Utf8PathBuf;
+
+use crate::conf::Target;
+
+/// The stage of a build that failed, with the details needed to handle it.
+///
+/// The command line exits with [`Error::exit_code`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The configuration couldn't be loaded, or the environment doesn't
+    /// support it, e.g. a target isn't installed
+    Config(anyhow::Error),
+    /// `cargo build` failed. The stderr is only captured when the
+    /// [`BuildObserver`](crate::BuildObserver) captures the output.
+    Cargo {
+        status: Option<i32>,
+        stderr: String,
+    },
+    /// cargo succeeded but didn't produce the library of a target
+    ArtifactMissing {
+        target: Target,
+        path: Utf8PathBuf,
+    },
+    /// Combining the libraries of a platform with lipo failed
+    Lipo {
+        platform: String,
+        output: Utf8PathBuf,
+        source: anyhow::Error,
+    },
+    /// A library contains objects built for another platform or a newer
+    /// deployment target
+    Validation {
+        path: Utf8PathBuf,
+        source: anyhow::Error,
+    },
+    /// Writing or converting an Info.plist failed
+    Plist {
+        path: Utf8PathBuf,
+        source: anyhow::Error,
+    },
+    /// Wrapping or signing the framework of a platform failed
+    Wrap {
+        platform: String,
+        framework: Utf8PathBuf,
+        source: anyhow::Error,
+    },
+    /// Creating or signing the XCFramework failed
+    Assemble {
+        path: Utf8PathBuf,
+        source: anyhow::Error,
+    },
+    /// Creating an archive of the XCFramework failed
+    Archive {
+        path: Utf8PathBuf,
+        source: anyhow::Error,
+    },
+    /// Two builds with `--verify-reproducible` produced different outputs
+    NotReproducible {
+        differences: Vec<String>,
+    },
+    /// `verify` found problems in an XCFramework
+    Verification {
+        path: Utf8PathBuf,
+        problems: Vec<String>,
+    },
+    /// `verify` or `inspect` couldn't read an XCFramework or its archive
+    Unreadable {
+        path: Utf8PathBuf,
+        source: anyhow::Error,
+    },
+    /// `doctor` found missing tools, SDKs or targets, named by the failed
+    /// checks
+    Diagnosis {
+        failed: Vec<String>,
+    },
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// The exit code of the command line for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Other(_) => 1,
+            Error::Config(_) => 2,
+            Error::Cargo { .. } => 3,
+            Error::ArtifactMissing { .. } => 4,
+            Error::Lipo { .. } => 5,
+            Error::Validation { .. } => 6,
+            Error::Plist { .. } => 7,
+            Error::Wrap { .. } => 8,
+            Error::Assemble { .. } => 9,
+            Error::Archive { .. } => 10,
+            Error::NotReproducible { .. } => 11,
+            Error::Verification { .. } => 12,
+            Error::Unreadable { .. } => 13,
+            Error::Diagnosis { .. } => 14,
+        }
+    }
+
+    /// Keep an error of a more specific stage raised further down, like a
+    /// plist error while wrapping, or attribute the error to `stage`.
+    pub(crate) fn or_stage(
+        error: anyhow::Error,
+        stage: impl FnOnce(anyhow::Error) -> Error,
+    ) -> Error {
+        match error.downcast::<Error>() {
+            Ok(error) => error,
+            Err(error) => stage(error),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(_) => write!(f, "invalid configuration"),
+            Error::Cargo { status, stderr } => {
+                match status {
+                    Some(code) => write!(f, "cargo build failed with exit code {code}")?,
+                    None => write!(f, "cargo build failed")?,
+                }
+                if !stderr.is_empty() {
+                    write!(f, "\n{stderr}")?;
+                }
+                Ok(())
+            }
+            Error::ArtifactMissing { target, path } => {
+                write!(f, "cargo didn't produce {path} for {target}")
+            }
+            Error::Lipo {
+                platform, output, ..
+            } => {
+                write!(f, "lipo failed to create {output} for {platform}")
+            }
+            Error::Validation { path, .. } => write!(f, "{path} failed validation"),
+            Error::Plist { path, .. } => write!(f, "writing {path} failed"),
+            Error::Wrap {
+                platform,
+                framework,
+                ..
+            } => write!(f, "wrapping {framework} for {platform} failed"),
+            Error::Assemble { path, .. } => write!(f, "creating {path} failed"),
+            Error::Archive { path, .. } => write!(f, "archiving {path} failed"),
+            Error::NotReproducible { differences } => write!(
+                f,
+                "The build is not reproducible:\n  {}",
+                differences.join("\n  ")
+            ),
+            Error::Verification { path, problems } => write!(
+                f,
+                "{path} has {} problem(s):\n  - {}",
+                problems.len(),
+                problems.join("\n  - ")
+            ),
+            Error::Unreadable { path, .. } => write!(f, "reading {path} failed"),
+            Error::Diagnosis { failed } => {
+                write!(f, "{} check(s) failed: {}", failed.len(), failed.join(", "))
+            }
+            Error::Other(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Config(source)
+            | Error::Lipo { source, .. }
+            | Error::Validation { source, .. }
+            | Error::Plist { source, .. }
+            | Error::Wrap { source, .. }
+            | Error::Assemble { source, .. }
+            | Error::Archive { source, .. }
+            | Error::Unreadable { source, .. } => Some(source.as_ref()),
+            Error::Other(error) => error.source(),
+            Error::Cargo { .. }
+            | Error::ArtifactMissing { .. }
+            | Error::NotReproducible { .. }
+            | Error::Verification { .. }
+            | Error::Diagnosis { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn stage_errors_raised_deeper_are_kept() {
+        let plist = Error::Plist {
+            path: "Info.plist".into(),
+            source: anyhow::anyhow!("disk full"),
+        };
+        let error = Err::<(), _>(plist).context("wrapping").unwrap_err();
+        let error = Error::or_stage(error, |source| Error::Wrap {
+            platform: "iOS".into(),
+            framework: "MyMath.framework".into(),
+            source,
+        });
+        assert_eq!(error.exit_code(), 7);
+        assert_eq!(error.to_string(), "writing Info.plist failed");
+
+        let error = Error::or_stage(anyhow::anyhow!("lipo: no such file"), |source| {
+            Error::Lipo {
+                platform: "macOS".into(),
+                output: "libmymath.a".into(),
+                source,
+            }
+        });
+        assert_eq!(error.exit_code(), 5);
+        let chain = format!("{:?}", anyhow::Error::from(error));
+        assert!(chain.contains("lipo: no such file"), "{chain}");
+    }
+}
@@ -3,158 +3,702 @@ mod cmd;
 mod conf;
 pub mod core;
 
-use core::platform::{ApplePlatform, Environment};
-use std::collections::HashMap;
+mod error;
+mod observer;
+mod plan;
+
+use core::archive::Archive;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
 
 pub use crate::conf::Configuration;
 use anyhow::{Context, Result};
 use camino_fs::*;
 use cmd::cargo;
-pub use conf::CliArgs;
-use conf::Target;
-pub use conf::{LibType, XCFrameworkConfiguration};
+pub use conf::ColorChoice;
+pub use conf::{CargoSettings, LibType, LibTypes, XCFrameworkConfiguration};
+pub use conf::{Cli, CliArgs, Command, DoctorArgs, InspectArgs, OutputFormat, VerifyArgs};
+pub use conf::{ConfigurationBuilder, PackageInfo, Target};
+pub use error::Error;
+pub use observer::{
+    BuildObserver, Event, JsonLinesObserver, QuietObserver, Stage, Stream, TerminalObserver,
+};
+pub use plan::{Artifact, BuildPlan, SlicePlan, XCFrameworkPlan, plan};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Produced {
     pub module_name: String,
+    /// The library type the XCFramework was built from
+    pub lib_type: LibType,
+    /// The variant that was built, if any
+    pub variant: Option<String>,
+    /// The XCFramework directory
     pub path: Utf8PathBuf,
-    pub is_zipped: bool,
+    /// The archives of the XCFramework, one per configured format
+    pub archives: Vec<Archive>,
 }
 
-pub fn build_from_cli(cli: CliArgs) -> Result<Produced> {
-    let config = Configuration::load(cli).context("loading configuration")?;
+/// Build the configured XCFrameworks, one per library type.
+pub fn build_from_cli(cli: CliArgs) -> Result<Vec<Produced>, Error> {
+    let config = Configuration::load(cli)
+        .context("loading configuration")
+        .map_err(Error::Config)?;
 
-    crate::build(&config)
+    if config.cli.dry_run {
+        let plan = plan(&config)?;
+        match config.cli.format.unwrap_or_default() {
+            OutputFormat::Table => print!("{plan}"),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&plan).map_err(|e| Error::Other(e.into()))?
+            ),
+        }
+        return Ok(vec![]);
+    }
+    if config.cli.verify_reproducible {
+        crate::verify_reproducible(&config)
+    } else {
+        crate::build(&config)
+    }
 }
 
-pub fn build(conf: &Configuration) -> Result<Produced> {
-    conf.build_dir.rm().context("cleaning build dir")?;
+/// Check an existing XCFramework and fail with the list of problems found.
+pub fn verify_from_cli(cli: VerifyArgs) -> Result<(), Error> {
+    println!("🔍 Verifying {} ...", cli.path);
+    let problems =
+        core::verify::verify_xcframework(&cli.path).map_err(|source| Error::Unreadable {
+            path: cli.path.clone(),
+            source,
+        })?;
+    if !problems.is_empty() {
+        return Err(Error::Verification {
+            path: cli.path,
+            problems,
+        });
+    }
+    println!("✅ {} is valid", cli.path);
+    Ok(())
+}
 
-    cargo::build(conf).context("running cargo build")?;
+/// Print the slices of an XCFramework directory or zip archive.
+pub fn inspect_from_cli(cli: InspectArgs) -> Result<(), Error> {
+    let inspection = core::inspect::inspect(&cli.path).map_err(|source| Error::Unreadable {
+        path: cli.path.clone(),
+        source,
+    })?;
+    match cli.format.unwrap_or_default() {
+        OutputFormat::Table => print!("{inspection}"),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&inspection).map_err(|e| Error::Other(e.into()))?
+        ),
+    }
+    Ok(())
+}
 
-    let libs = {
-        let conf = &conf;
-        let libs_dir = conf.build_dir.join("libs");
-        libs_dir.mkdirs()?;
+/// Report which of the tools, SDKs and targets needed for a build are
+/// available, and fail if any is missing.
+pub fn doctor_from_cli(cli: DoctorArgs) -> Result<(), Error> {
+    let report = cmd::doctor::diagnose(cli.manifest_path.as_deref(), cli.package.as_deref());
+    match cli.format.unwrap_or_default() {
+        OutputFormat::Table => print!("{report}"),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(|e| Error::Other(e.into()))?
+        ),
+    }
+    let failed = report.failed();
+    if !failed.is_empty() {
+        return Err(Error::Diagnosis { failed });
+    }
+    Ok(())
+}
 
-        let mut platform_lib_paths = HashMap::new();
-        if conf.cargo_section.iOS {
-            let lib_paths = lib_paths_for_targets(conf, &conf.cargo_section.iOS_targets)?;
-            platform_lib_paths.insert(ApplePlatform::IOS(Environment::Device), lib_paths);
-        }
-        if conf.cargo_section.simulators {
-            let lib_paths = lib_paths_for_targets(conf, &conf.cargo_section.iOS_simulator_targets)?;
-            platform_lib_paths.insert(ApplePlatform::IOS(Environment::Simulator), lib_paths);
+/// Build twice, recompiling the package in between, and fail if the outputs differ.
+pub fn verify_reproducible(conf: &Configuration) -> Result<Vec<Produced>, Error> {
+    let first = crate::build(conf)?;
+
+    let snapshot_dir = conf.target_dir.join("xcframework-reproducible");
+    let snapshots = (|| -> Result<Vec<Utf8PathBuf>> {
+        snapshot_dir.rm()?;
+        snapshot_dir.mkdirs()?;
+        let mut snapshots = vec![];
+        for output in first.iter().flat_map(Produced::outputs) {
+            let snapshot = snapshot_dir.join(output.file_name().context("missing output name")?);
+            output.mv(&snapshot)?;
+            snapshots.push(snapshot);
         }
-        if conf.cargo_section.macOS {
-            let lib_paths = lib_paths_for_targets(conf, &conf.cargo_section.macOS_targets)?;
-            platform_lib_paths.insert(ApplePlatform::MacOS, lib_paths);
+        cargo::clean_package(conf).context("cleaning package")?;
+        Ok(snapshots)
+    })()
+    .map_err(Error::Other)?;
+
+    let second = crate::build(conf)?;
+
+    let diffs = (|| -> Result<Vec<String>> {
+        let outputs = second.iter().flat_map(Produced::outputs);
+        let mut diffs = vec![];
+        for (snapshot, output) in snapshots.iter().zip(outputs) {
+            diffs.extend(core::reproducible::diff_outputs(snapshot, output)?);
         }
+        snapshot_dir.rm()?;
+        Ok(diffs)
+    })()
+    .map_err(Error::Other)?;
+    if !diffs.is_empty() {
+        return Err(Error::NotReproducible { differences: diffs });
+    }
+    for produced in &second {
+        conf.emit(Event::StageFinished {
+            stage: Stage::VerifyReproducible,
+            subject: produced.module_name.clone(),
+            output: Some(produced.path.clone()),
+            checksum: None,
+        });
+    }
+    Ok(second)
+}
+
+impl Produced {
+    /// The XCFramework followed by its archives
+    fn outputs(&self) -> impl Iterator<Item = &Utf8PathBuf> {
+        std::iter::once(&self.path).chain(self.archives.iter().map(|a| &a.path))
+    }
+}
 
-        let ending = conf.lib_type.file_ending();
-        let name = &conf.lib_name.replace('-', "_");
-        let output_lib_name = format!("lib{name}.{ending}");
+/// Build the package once and assemble an XCFramework for each library type.
+pub fn build(conf: &Configuration) -> Result<Vec<Produced>, Error> {
+    let plan = plan(conf)?;
+    conf.build_dir
+        .rm()
+        .context("cleaning build dir")
+        .map_err(Error::Other)?;
 
-        crate::core::lipo_create_platform_libraries(
-            &platform_lib_paths,
-            &output_lib_name,
-            &libs_dir,
-        )
+    if let Some(variant) = &conf.variant {
+        conf.emit(Event::Variant {
+            name: variant.clone(),
+        });
+    }
+    for (platform, targets) in conf.cargo_section.platform_targets() {
+        conf.emit(Event::Targets {
+            platform: platform.platform_display_name().into(),
+            targets: targets.to_vec(),
+        });
+    }
+    for message in &plan.warnings {
+        conf.emit(Event::Warning {
+            message: message.clone(),
+        });
     }
-    .context("lipo: assembling libraries")?;
 
-    let bundle_name = conf.module_name().context("finding module name")?;
+    cmd::rustup::check_targets(conf)
+        .context("checking installed targets")
+        .map_err(Error::Config)?;
+    // Invocations sharing the target directory wait for each other's lock,
+    // so they run one after another, as documented in the README
+    for command in &plan.cargo {
+        let targets = command
+            .targets
+            .iter()
+            .map(Target::as_str)
+            .collect::<Vec<_>>();
+        let subject = targets.join(", ");
+        conf.emit(Event::StageStarted {
+            stage: Stage::Cargo,
+            subject: subject.clone(),
+        });
+        command.run(conf.observer.as_ref()).map_err(|error| {
+            match error.downcast::<cmd::CommandError>() {
+                Ok(error) => Error::Cargo {
+                    status: error.status,
+                    stderr: error.stderr,
+                },
+                Err(error) => Error::Cargo {
+                    status: None,
+                    stderr: format!("{error:#}"),
+                },
+            }
+        })?;
+        conf.emit(Event::StageFinished {
+            stage: Stage::Cargo,
+            subject,
+            output: None,
+            checksum: None,
+        });
+    }
 
-    let crate_type = match conf.lib_type {
-        conf::LibType::StaticLib => &core::CrateType::Staticlib,
-        conf::LibType::CDyLib => &core::CrateType::Cdylib,
-    };
+    let produced = plan
+        .xcframeworks
+        .iter()
+        .map(|xcframework| assemble(conf, xcframework))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    conf.build_dir
+        .rm()
+        .context("cleaning build dir")
+        .map_err(Error::Other)?;
+    Ok(produced)
+}
 
-    let framework_paths = libs
+/// Assemble the XCFramework of one library type from the cargo build outputs.
+fn assemble(conf: &Configuration, plan: &XCFrameworkPlan) -> Result<Produced, Error> {
+    for artifact in plan.slices.iter().flat_map(|slice| &slice.artifacts) {
+        if !artifact.path.is_file() {
+            return Err(Error::ArtifactMissing {
+                target: artifact.target,
+                path: artifact.path.clone(),
+            });
+        }
+    }
+
+    let module_name = &plan.module_name;
+
+    let resources = (|| -> Result<Vec<core::BundleResource>> {
+        if !plan.modulemap.exists() {
+            let original = plan::get_module_path(&conf.cargo_section.include_dir)?;
+            let content = original.read_string()?;
+            let renamed = core::modulemap::rename_module(&content, module_name)
+                .with_context(|| format!("renaming the module in {original}"))?;
+            plan.modulemap.write(renamed)?;
+        }
+
+        let mut resources = vec![];
+        if let Some(manifest) = &conf.cargo_section.privacy_manifest {
+            let path = conf.build_dir.join(core::privacy::FILE_NAME);
+            manifest.write(&path).context("writing privacy manifest")?;
+            resources.push(core::BundleResource {
+                source: path,
+                destination: core::privacy::FILE_NAME.into(),
+            });
+        }
+        Ok(resources)
+    })()
+    .map_err(Error::Other)?;
+
+    let mut framework_paths = process_slices(conf, plan, &resources)?;
+    let signing = conf.cargo_section.signing.as_ref();
+
+    // The order of the frameworks decides the order in the XCFramework's Info.plist
+    framework_paths.sort();
+
+    let path = plan.output.clone();
+    conf.emit(Event::StageStarted {
+        stage: Stage::Assemble,
+        subject: module_name.clone(),
+    });
+    (|| -> Result<()> {
+        let xcframework_path =
+            crate::core::create_xcframework(framework_paths, module_name, &conf.build_dir)
+                .context("creating xcframework")?;
+
+        if let Some(signing) = signing {
+            sign(conf.observer.as_ref(), signing, &xcframework_path)
+                .context("signing xcframework")?;
+        }
+
+        path.rm()?;
+        // Renaming instead of copying keeps the macOS framework symlinks intact
+        xcframework_path.mv(&path)?;
+        Ok(())
+    })()
+    .map_err(|source| Error::Assemble {
+        path: path.clone(),
+        source,
+    })?;
+    conf.emit(Event::StageFinished {
+        stage: Stage::Assemble,
+        subject: module_name.clone(),
+        output: Some(path.clone()),
+        checksum: None,
+    });
+
+    let archives = conf
+        .cargo_section
+        .archive_formats()
         .into_iter()
-        .map(|(platform, lib_path)| {
-            let include_dir = &conf.cargo_section.include_dir;
-            let header_paths = get_header_paths(include_dir)?;
-            let module_path = get_module_path(include_dir)?;
-            let frameworks_dir = conf.target_dir.join("frameworks");
-            frameworks_dir.mkdirs()?;
-
-            core::wrap_as_framework(
-                platform,
-                crate_type,
-                &lib_path,
-                header_paths,
-                module_path,
-                &bundle_name,
-                &frameworks_dir,
+        .zip(&plan.archives)
+        .map(|(format, archive)| {
+            conf.emit(Event::StageStarted {
+                stage: Stage::Archive,
+                subject: format.to_string(),
+            });
+            let compressed = core::compress_xcframework(
+                &path,
+                &conf.target_dir,
+                format,
+                conf.cargo_section.compression_level,
             )
+            .map_err(|source| Error::Archive {
+                path: archive.clone(),
+                source,
+            })?;
+            conf.emit(Event::StageFinished {
+                stage: Stage::Archive,
+                subject: format.to_string(),
+                output: Some(compressed.path.clone()),
+                checksum: Some(compressed.checksum.clone()),
+            });
+            Ok(compressed)
         })
-        .collect::<anyhow::Result<Vec<_>>>()
-        .context("collecting framework paths")?;
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let xcframework_path =
-        crate::core::create_xcframework(framework_paths, &conf.module_name()?, &conf.build_dir)
-            .context("creating xcframework")?;
+    Ok(Produced {
+        module_name: module_name.clone(),
+        lib_type: plan.lib_type.clone(),
+        variant: conf.variant.clone(),
+        path,
+        archives,
+    })
+}
 
-    let module_name = conf.module_name()?;
+/// Lipo, validate and wrap the slices of `plan`, `conf.jobs()` at a time.
+///
+/// The events of a slice are reported once it is done, in the order of the
+/// slices. The first failure stops the slices that haven't started yet and
+/// the remaining steps of the running ones.
+fn process_slices(
+    conf: &Configuration,
+    plan: &XCFrameworkPlan,
+    resources: &[core::BundleResource],
+) -> Result<Vec<Utf8PathBuf>, Error> {
+    let cancelled = AtomicBool::new(false);
+    let jobs = conf.jobs().min(plan.slices.len());
+    if jobs <= 1 {
+        return plan
+            .slices
+            .iter()
+            .map(|slice| {
+                process_slice(
+                    conf,
+                    plan,
+                    slice,
+                    resources,
+                    conf.observer.as_ref(),
+                    &cancelled,
+                )
+                .map(|path| path.expect("only cancelled by a failure"))
+            })
+            .collect();
+    }
 
-    let path = if conf.cargo_section.zip {
-        core::compress_xcframework(&xcframework_path, &conf.target_dir)?
-    } else {
-        let to = conf.target_dir.join(format!("{module_name}.xcframework"));
-        to.rm()?;
-        xcframework_path.mv(&to)?;
-        to
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (next, cancelled) = (&next, &cancelled);
+            scope.spawn(move || {
+                while !cancelled.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(slice) = plan.slices.get(index) else {
+                        break;
+                    };
+                    let recorder = observer::Recorder::default();
+                    let result = process_slice(conf, plan, slice, resources, &recorder, cancelled);
+                    if result.is_err() {
+                        cancelled.store(true, Ordering::Relaxed);
+                    }
+                    // The receiver lives until every worker is done
+                    let _ = sender.send((index, recorder.into_events(), result));
+                }
+            });
+        }
+        drop(sender);
+
+        let mut done = (0..plan.slices.len()).map(|_| None).collect::<Vec<_>>();
+        let mut reported = 0;
+        let mut paths = vec![];
+        let mut failure = None;
+        for (index, events, result) in receiver {
+            match result {
+                Ok(Some(path)) => paths.push(path),
+                Ok(None) => {}
+                Err(error) => {
+                    failure.get_or_insert(error);
+                }
+            }
+            done[index] = Some(events);
+            // Slices start in order, so every started slice gets reported
+            while let Some(Some(events)) = done.get_mut(reported).map(Option::take) {
+                for event in &events {
+                    conf.emit(event.clone());
+                }
+                reported += 1;
+            }
+        }
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(paths),
+        }
+    })
+}
+
+/// Lipo, validate and wrap the framework of one platform. Returns `None`
+/// when another slice failed in the meantime.
+fn process_slice(
+    conf: &Configuration,
+    plan: &XCFrameworkPlan,
+    slice: &SlicePlan,
+    resources: &[core::BundleResource],
+    observer: &dyn BuildObserver,
+    cancelled: &AtomicBool,
+) -> Result<Option<Utf8PathBuf>, Error> {
+    let platform = slice.platform.platform_display_name();
+    if slice.artifacts.len() > 1 {
+        observer.event(&Event::StageStarted {
+            stage: Stage::Lipo,
+            subject: platform.into(),
+        });
+        core::lipo_create_library(&slice.artifact_paths(), &slice.library).map_err(|source| {
+            Error::Lipo {
+                platform: platform.into(),
+                output: slice.library.clone(),
+                source,
+            }
+        })?;
+        observer.event(&Event::StageFinished {
+            stage: Stage::Lipo,
+            subject: platform.into(),
+            output: Some(slice.library.clone()),
+            checksum: None,
+        });
+    }
+    if cancelled.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    let subject = slice.library.to_string();
+    observer.event(&Event::StageStarted {
+        stage: Stage::Validate,
+        subject: subject.clone(),
+    });
+    core::validate_library(&slice.platform, &slice.library, &slice.deployment_target).map_err(
+        |source| Error::Validation {
+            path: slice.library.clone(),
+            source,
+        },
+    )?;
+    observer.event(&Event::StageFinished {
+        stage: Stage::Validate,
+        subject,
+        output: None,
+        checksum: None,
+    });
+    if cancelled.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    let crate_type = match plan.lib_type {
+        conf::LibType::StaticLib => &core::CrateType::Staticlib,
+        conf::LibType::CDyLib => &core::CrateType::Cdylib,
     };
+    let frameworks_dir = conf.target_dir.join("frameworks");
+    let wrap = || -> Result<Utf8PathBuf> {
+        let mut resources = resources.to_vec();
+        for pattern in conf.cargo_section.resources_for(&slice.platform) {
+            resources.extend(core::BundleResource::from_glob(pattern)?);
+        }
+        let contents = core::FrameworkContents {
+            header_paths: plan.headers.clone(),
+            module_path: plan.modulemap.clone(),
+            resources,
+        };
+        frameworks_dir.mkdirs()?;
 
-    conf.build_dir.rm().context("cleaning build dir")?;
+        let info = conf.bundle_info(&slice.platform);
+        observer.event(&Event::StageStarted {
+            stage: Stage::Wrap,
+            subject: platform.into(),
+        });
 
-    Ok(Produced {
-        module_name,
-        path,
-        is_zipped: conf.cargo_section.zip,
+        let path = core::wrap_as_framework(
+            slice.platform.clone(),
+            crate_type,
+            &slice.library,
+            &contents,
+            &info,
+            &plan.module_name,
+            &frameworks_dir,
+        )?;
+        observer.event(&Event::StageFinished {
+            stage: Stage::Wrap,
+            subject: platform.into(),
+            output: Some(path.clone()),
+            checksum: None,
+        });
+        if let Some(signing) = &conf.cargo_section.signing {
+            sign(observer, signing, &path).context("signing framework")?;
+        }
+        Ok(path)
+    };
+    wrap().map(Some).map_err(|error| {
+        if let Some(core::plist::WritingPlist(path)) = error.downcast_ref() {
+            return Error::Plist {
+                path: path.clone(),
+                source: error,
+            };
+        }
+        Error::or_stage(error, |source| Error::Wrap {
+            platform: platform.into(),
+            framework: slice.framework.clone(),
+            source,
+        })
     })
 }
 
-fn get_header_paths(include_dir: &Utf8PathBuf) -> Result<Vec<Utf8PathBuf>> {
-    let mut header_paths = Vec::new();
-    let pattern = format!("{}/**/*.h", include_dir);
+fn sign(
+    observer: &dyn BuildObserver,
+    signing: &core::signing::SigningConfig,
+    path: &Utf8Path,
+) -> Result<()> {
+    observer.event(&Event::StageStarted {
+        stage: Stage::Sign,
+        subject: path.to_string(),
+    });
+    core::signing::sign_bundle(signing, &core::signing::ProcessRunner, path)?;
+    observer.event(&Event::StageFinished {
+        stage: Stage::Sign,
+        subject: path.to_string(),
+        output: None,
+        checksum: None,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
 
-    for entry in glob::glob(&pattern)? {
-        match entry {
-            Ok(path) => header_paths.push(Utf8PathBuf::from_path_buf(path).unwrap()),
-            Err(e) => println!("{:?}", e),
+    struct Shared(Arc<observer::Recorder>);
+
+    impl BuildObserver for Shared {
+        fn event(&self, event: &Event) {
+            self.0.event(event)
         }
     }
 
-    Ok(header_paths)
-}
+    #[test]
+    fn slices_report_in_order_and_stop_at_the_first_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let recorder = Arc::new(observer::Recorder::default());
+        let conf = conf::testing::example(serde_json::json!({ "include-dir": "include" }))
+            .targets(
+                [
+                    "aarch64-apple-ios",
+                    "aarch64-apple-ios-sim",
+                    "aarch64-apple-darwin",
+                ]
+                .map(|t| t.parse().unwrap()),
+            )
+            .output_dir(&out)
+            .jobs(2)
+            .observer(Shared(recorder.clone()))
+            .build()
+            .unwrap();
+        let plan = plan(&conf).unwrap();
+        let xcframework = &plan.xcframeworks[0];
+        for artifact in xcframework.slices.iter().flat_map(|s| &s.artifacts) {
+            artifact.path.parent().unwrap().mkdirs().unwrap();
+            artifact.path.write("not a library").unwrap();
+        }
+
+        let error = process_slices(&conf, xcframework, &[]).unwrap_err();
+        assert_eq!(error.exit_code(), 6);
 
-fn get_module_path(include_dir: &Utf8PathBuf) -> Result<Utf8PathBuf> {
-    let pattern = format!("{include_dir}/**/*.modulemap");
-    let mut glob = glob::glob(&pattern)?;
-    let module_path = glob.next().context("modulemap not found")??;
-    if glob.next().is_some() {
-        anyhow::bail!("multiple modulemaps found");
+        drop(conf);
+        let events = Arc::into_inner(recorder).unwrap().into_events();
+        let validated = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::StageStarted {
+                    stage: Stage::Validate,
+                    subject,
+                } => Some(subject.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let libraries = xcframework
+            .slices
+            .iter()
+            .map(|s| s.library.as_str())
+            .collect::<Vec<_>>();
+        // The failure of a slice cancels the ones not started yet
+        assert!(
+            (1..libraries.len()).contains(&validated.len()),
+            "{validated:?}"
+        );
+        assert_eq!(validated, libraries[..validated.len()]);
+        assert!(!events.iter().any(|event| matches!(
+            event,
+            Event::StageStarted {
+                stage: Stage::Wrap,
+                ..
+            }
+        )));
     }
 
-    Ok(Utf8PathBuf::from_path_buf(module_path).unwrap())
-}
+    #[test]
+    fn verify_errors_have_their_own_exit_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.path().join("MyMath.xcframework")).unwrap();
+        path.mkdirs().unwrap();
+
+        let verify = || verify_from_cli(VerifyArgs { path: path.clone() }).unwrap_err();
+        let error = verify();
+        assert!(matches!(error, Error::Unreadable { .. }), "{error:?}");
+        assert_eq!(error.exit_code(), 13);
+
+        let mut info = plist::Dictionary::new();
+        info.insert(
+            "AvailableLibraries".into(),
+            plist::Value::Array(vec!["ios-arm64".into()]),
+        );
+        plist::Value::Dictionary(info)
+            .to_file_xml(path.join("Info.plist"))
+            .unwrap();
+        let error = verify();
+        assert_eq!(error.exit_code(), 12);
+        assert!(
+            error.to_string().ends_with(
+                "has 1 problem(s):\n  - AvailableLibraries contains a non-dictionary entry"
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn libraries_are_validated_against_the_platform_deployment_target() {
+        use crate::core::macho::testing::*;
 
-fn lib_paths_for_targets(conf: &Configuration, targets: &[Target]) -> Result<Vec<Utf8PathBuf>> {
-    let mut paths = vec![];
+        let dir = tempfile::tempdir().unwrap();
+        let out = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+        let section = serde_json::json!({
+            "include-dir": "include",
+            "platform": { "macOS": { "env": { "MACOSX_DEPLOYMENT_TARGET": "11.0" } } },
+        });
+        let conf = conf::testing::example(section)
+            .targets(["x86_64-apple-darwin".parse().unwrap()])
+            .output_dir(&out)
+            .build()
+            .unwrap();
+        let plan = plan(&conf).unwrap();
+        let slice = &plan.xcframeworks[0].slices[0];
+        assert_eq!(slice.deployment_target, "11.0");
+        let info = conf.bundle_info(&slice.platform);
+        assert_eq!(info.minimum_os_version.as_deref(), Some("11.0"));
 
-    let target_dir = &conf.target_dir;
-    let profile = conf.profile();
-    let ending = conf.lib_type.file_ending();
-    let name = &conf.lib_name.replace('-', "_");
+        // An object built by cargo with the platform's deployment target
+        slice.library.parent().unwrap().mkdirs().unwrap();
+        slice
+            .library
+            .write(archive(&[("lib.o", object(X86_64, 1, (11, 0)))]))
+            .unwrap();
+        core::validate_library(&slice.platform, &slice.library, &slice.deployment_target).unwrap();
 
-    for target in targets {
-        let path = target_dir
-            .join(target.as_str())
-            .join(profile)
-            .join(format!("lib{name}.{ending}"));
-        paths.push(path)
+        let older = core::validate_library(&slice.platform, &slice.library, "10.12");
+        assert!(older.is_err());
     }
-    Ok(paths)
 }
@@ -1,10 +1,16 @@
-use xcframework::CliArgs;
+use xcframework::{Cli, Command};
 
 fn main() {
-    let args = CliArgs::from_env_or_exit();
+    let result = match Cli::from_env_or_exit().subcommand {
+        Command::Build(args) => xcframework::build_from_cli(args).map(drop),
+        Command::Verify(args) => xcframework::verify_from_cli(args),
+        Command::Inspect(args) => xcframework::inspect_from_cli(args),
+        Command::Doctor(args) => xcframework::doctor_from_cli(args),
+    };
 
-    if let Err(e) = xcframework::build_from_cli(args) {
-        eprintln!("{:?}", e);
-        std::process::exit(1);
+    if let Err(e) = result {
+        let code = e.exit_code();
+        eprintln!("{:?}", anyhow::Error::from(e));
+        std::process::exit(code);
     }
 }
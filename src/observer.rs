@@ -0,0 +1,351 @@
+//! Progress reporting of a build.
+
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+
+use camino_fs::Utf8PathBuf;
+use serde::Serialize;
+use yansi::Paint;
+
+use crate::conf::{CliArgs, ColorChoice, OutputFormat, Target};
+
+/// A step of the build, reported by [`Event::StageStarted`] and
+/// [`Event::StageFinished`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    InstallTargets,
+    Cargo,
+    Lipo,
+    Validate,
+    Wrap,
+    Sign,
+    Assemble,
+    Archive,
+    VerifyReproducible,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// The `[package.metadata.xcframework.variants.<name>]` being built
+    Variant {
+        name: String,
+    },
+    /// The targets built for a platform
+    Targets {
+        platform: String,
+        targets: Vec<Target>,
+    },
+    /// A stage started for `subject`, a platform, bundle or archive format
+    StageStarted {
+        stage: Stage,
+        subject: String,
+    },
+    StageFinished {
+        stage: Stage,
+        subject: String,
+        output: Option<Utf8PathBuf>,
+        /// The SHA-256 checksum of an archive
+        checksum: Option<String>,
+    },
+    CommandSpawned {
+        program: String,
+        args: Vec<String>,
+    },
+    /// A line a command printed, when its output is captured
+    CommandOutput {
+        program: String,
+        stream: Stream,
+        line: String,
+    },
+    CommandFinished {
+        program: String,
+        args: Vec<String>,
+        status: Option<i32>,
+        success: bool,
+    },
+    Warning {
+        message: String,
+    },
+}
+
+/// Receives the events of a build.
+pub trait BuildObserver: Send + Sync {
+    fn event(&self, event: &Event);
+
+    /// Whether the output of the commands run by the build, like cargo,
+    /// should be captured and reported as [`Event::CommandOutput`] instead
+    /// of going to the terminal.
+    fn captures_output(&self) -> bool {
+        false
+    }
+}
+
+impl fmt::Debug for dyn BuildObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BuildObserver")
+    }
+}
+
+/// Prints the progress of the build for humans.
+#[derive(Debug)]
+pub struct TerminalObserver {
+    color: bool,
+}
+
+impl TerminalObserver {
+    pub fn new(color: ColorChoice) -> Self {
+        let color = match color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => !no_color() && io::stdout().is_terminal(),
+        };
+        Self { color }
+    }
+
+    fn paint<'a>(
+        &self,
+        text: &'a str,
+        style: impl Fn(&'a str) -> yansi::Painted<&'a str>,
+    ) -> String {
+        if self.color {
+            style(text).to_string()
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// The line printed for an event, if any.
+    fn message(&self, event: &Event) -> Option<String> {
+        Some(match event {
+            Event::Variant { name } => format!("🎯 Variant: {name}"),
+            Event::Targets { platform, targets } => {
+                let targets = targets.iter().map(Target::as_str).collect::<Vec<_>>();
+                format!("🎯 {platform}: {}", targets.join(", "))
+            }
+            Event::StageStarted { stage, subject } => match stage {
+                Stage::InstallTargets => format!("🧰 Installing the targets {subject} ..."),
+                Stage::Lipo => format!("🍭 Running lipo create for platform: {subject} ..."),
+                Stage::Wrap => format!("📦 Wrapping {subject} libraries as framework ..."),
+                Stage::Sign => format!("🔏 Signing {subject} ..."),
+                Stage::Assemble => "🧰 Running create xcframework...".into(),
+                Stage::Archive => format!("📦 Compressing XCFramework as {subject} ..."),
+                Stage::Cargo | Stage::Validate | Stage::VerifyReproducible => return None,
+            },
+            Event::StageFinished {
+                stage,
+                subject,
+                output,
+                checksum,
+            } => {
+                let output = output.as_ref().map(|o| o.as_str()).unwrap_or_default();
+                match stage {
+                    Stage::Lipo => format!(
+                        "✅ Run lipo create success, platform: {subject}, output:\n{output}"
+                    ),
+                    Stage::Wrap => {
+                        format!("✅ Wrapped artifacts as framework success, output:\n{output}")
+                    }
+                    Stage::Sign => format!("✅ Signed {subject}"),
+                    Stage::Assemble => {
+                        format!("✅ Run create xcframework success, output:\n{output}")
+                    }
+                    Stage::Archive => format!(
+                        "✅ Compressed XCFramework success, output:\n{output}\nchecksum: {}",
+                        checksum.as_deref().unwrap_or_default()
+                    ),
+                    Stage::VerifyReproducible => {
+                        format!("✅ The build is reproducible: {output}")
+                    }
+                    Stage::InstallTargets | Stage::Cargo | Stage::Validate => return None,
+                }
+            }
+            Event::CommandSpawned { .. } => return None,
+            Event::CommandOutput { line, .. } => line.clone(),
+            Event::CommandFinished {
+                program,
+                args,
+                success,
+                ..
+            } => {
+                let cmd = format!("{program} {}", args.join(" "));
+                let cmd = self.paint(&cmd, |c| c.dim());
+                if *success {
+                    let prefix = self.paint(" XCFramework", |p| p.green().bold());
+                    format!("{prefix} done {cmd}")
+                } else {
+                    let prefix = self.paint(" XCFramework", |p| p.red().bold());
+                    format!("{prefix} error when running: {cmd}")
+                }
+            }
+            Event::Warning { message } => {
+                format!(
+                    "{} {message}",
+                    self.paint("warning:", |w| w.yellow().bold())
+                )
+            }
+        })
+    }
+}
+
+impl BuildObserver for TerminalObserver {
+    fn event(&self, event: &Event) {
+        let Some(message) = self.message(event) else {
+            return;
+        };
+        match event {
+            Event::Warning { .. }
+            | Event::CommandOutput {
+                stream: Stream::Stderr,
+                ..
+            } => eprintln!("{message}"),
+            _ => println!("{message}"),
+        }
+    }
+}
+
+/// Only prints warnings, and captures the output of commands.
+#[derive(Debug)]
+pub struct QuietObserver;
+
+impl BuildObserver for QuietObserver {
+    fn event(&self, event: &Event) {
+        if let Event::Warning { message } = event {
+            eprintln!("warning: {message}");
+        }
+    }
+
+    fn captures_output(&self) -> bool {
+        true
+    }
+}
+
+/// Writes every event as a line of JSON, for tools driving the build.
+#[derive(Debug)]
+pub struct JsonLinesObserver<W = io::Stdout> {
+    out: Mutex<W>,
+}
+
+impl JsonLinesObserver {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write + Send> JsonLinesObserver<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<W: Write + Send> BuildObserver for JsonLinesObserver<W> {
+    fn event(&self, event: &Event) {
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // Progress reporting never fails the build
+        let _ = serde_json::to_writer(&mut *out, event);
+        let _ = writeln!(out);
+        let _ = out.flush();
+    }
+
+    fn captures_output(&self) -> bool {
+        true
+    }
+}
+
+/// Keeps the events of a platform slice processed in parallel, to report
+/// them in order once it is done.
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    events: Mutex<Vec<Event>>,
+}
+
+impl Recorder {
+    pub(crate) fn into_events(self) -> Vec<Event> {
+        self.events.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl BuildObserver for Recorder {
+    fn event(&self, event: &Event) {
+        let mut events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        events.push(event.clone());
+    }
+}
+
+/// The observer selected by the command line flags.
+pub(crate) fn from_cli(cli: &CliArgs) -> Arc<dyn BuildObserver> {
+    if cli.format == Some(OutputFormat::Json) {
+        Arc::new(JsonLinesObserver::stdout())
+    } else if cli.quiet {
+        Arc::new(QuietObserver)
+    } else {
+        Arc::new(TerminalObserver::new(cli.color.unwrap_or_default()))
+    }
+}
+
+/// Whether the `NO_COLOR` environment variable disables colors.
+pub(crate) fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        let observer = JsonLinesObserver::new(Vec::new());
+        observer.event(&Event::StageStarted {
+            stage: Stage::Lipo,
+            subject: "iOS Simulator".into(),
+        });
+        observer.event(&Event::Targets {
+            platform: "macOS".into(),
+            targets: vec![Target::MacosArm64],
+        });
+        let out = String::from_utf8(observer.into_inner()).unwrap();
+        assert_eq!(
+            out,
+            "{\"event\":\"stage-started\",\"stage\":\"lipo\",\"subject\":\"iOS Simulator\"}\n\
+             {\"event\":\"targets\",\"platform\":\"macOS\",\"targets\":[\"aarch64-apple-darwin\"]}\n"
+        );
+    }
+
+    #[test]
+    fn terminal_messages_without_color() {
+        let observer = TerminalObserver::new(ColorChoice::Never);
+        let finished = Event::CommandFinished {
+            program: "cargo".into(),
+            args: vec!["build".into()],
+            status: Some(0),
+            success: true,
+        };
+        assert_eq!(
+            observer.message(&finished).unwrap(),
+            " XCFramework done cargo build"
+        );
+        let started = Event::StageStarted {
+            stage: Stage::Cargo,
+            subject: "mymath".into(),
+        };
+        assert_eq!(observer.message(&started), None);
+
+        let colored = TerminalObserver::new(ColorChoice::Always);
+        assert!(colored.message(&finished).unwrap().contains('\u{1b}'));
+    }
+}
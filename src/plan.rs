@@ -0,0 +1,265 @@
+//! The steps of a build, resolved from the configuration without running
+//! anything.
+
+use std::fmt;
+
+use anyhow::{Context, Result};
+use camino_fs::*;
+use serde::{Serialize, Serializer};
+
+use crate::Error;
+use crate::cmd::cargo::{self, CargoCommand};
+use crate::conf::{Configuration, LibType, Target};
+use crate::core::platform::ApplePlatform;
+
+/// Everything a build does: the cargo invocations followed by the assembly
+/// of an XCFramework per library type.
+#[derive(Debug, Serialize)]
+pub struct BuildPlan {
+    pub variant: Option<String>,
+    /// One invocation, or one per group of targets with the same cargo
+    /// settings
+    pub cargo: Vec<CargoCommand>,
+    pub xcframeworks: Vec<XCFrameworkPlan>,
+    /// Problems found while planning that don't stop the build
+    pub warnings: Vec<String>,
+}
+
+/// The assembly of the XCFramework of one library type.
+#[derive(Debug, Serialize)]
+pub struct XCFrameworkPlan {
+    pub module_name: String,
+    pub lib_type: LibType,
+    /// The modulemap copied into the frameworks, with the module renamed to
+    /// `module_name` if it declares another name
+    pub modulemap: Utf8PathBuf,
+    /// The headers copied into the frameworks
+    pub headers: Vec<Utf8PathBuf>,
+    pub slices: Vec<SlicePlan>,
+    /// The XCFramework directory
+    pub output: Utf8PathBuf,
+    pub archives: Vec<Utf8PathBuf>,
+}
+
+/// The framework of one platform.
+#[derive(Debug, Serialize)]
+pub struct SlicePlan {
+    #[serde(serialize_with = "platform_display_name")]
+    pub platform: ApplePlatform,
+    /// The libraries cargo builds for the targets of the platform
+    pub artifacts: Vec<Artifact>,
+    /// The library combining the artifacts with lipo, or the only artifact
+    pub library: Utf8PathBuf,
+    /// The deployment target the library is validated against and the
+    /// frameworks' `MinimumOSVersion`
+    pub deployment_target: String,
+    pub framework: Utf8PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Artifact {
+    pub target: Target,
+    pub path: Utf8PathBuf,
+    /// The features enabled with `--features`
+    pub features: Vec<String>,
+    pub default_features: bool,
+}
+
+impl SlicePlan {
+    pub fn artifact_paths(&self) -> Vec<Utf8PathBuf> {
+        self.artifacts.iter().map(|a| a.path.clone()).collect()
+    }
+}
+
+fn platform_display_name<S: Serializer>(
+    platform: &ApplePlatform,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(platform.platform_display_name())
+}
+
+/// Resolve every step of the build of `conf`.
+pub fn plan(conf: &Configuration) -> Result<BuildPlan, Error> {
+    let mut warnings = vec![];
+    let xcframeworks = conf
+        .lib_types
+        .iter()
+        .map(|lib_type| plan_xcframework(conf, lib_type, &mut warnings))
+        .collect::<Result<Vec<_>>>()
+        .context("planning the build")
+        .map_err(Error::Config)?;
+    Ok(BuildPlan {
+        variant: conf.variant.clone(),
+        cargo: cargo::build_commands(conf),
+        xcframeworks,
+        warnings,
+    })
+}
+
+fn plan_xcframework(
+    conf: &Configuration,
+    lib_type: &LibType,
+    warnings: &mut Vec<String>,
+) -> Result<XCFrameworkPlan> {
+    let module_name = conf
+        .flavor_module_name(lib_type)
+        .context("finding module name")?;
+    let include_dir = &conf.cargo_section.include_dir;
+    let modulemap = if module_name == conf.module_name()? {
+        get_module_path(include_dir)?
+    } else {
+        conf.build_dir.join(format!("{module_name}.modulemap"))
+    };
+
+    let name = conf.lib_name.replace('-', "_");
+    let lib_file = format!("lib{name}.{}", lib_type.file_ending());
+    let frameworks_dir = conf.target_dir.join("frameworks");
+    let slices = conf
+        .cargo_section
+        .platform_targets()
+        .into_iter()
+        .map(|(platform, targets)| {
+            let artifacts = targets
+                .iter()
+                .map(|target| {
+                    let settings = conf.cargo_section.cargo_settings_for(*target);
+                    let (features, default_features) = cargo::features(conf, &settings);
+                    Artifact {
+                        target: *target,
+                        path: conf
+                            .target_dir
+                            .join(target.as_str())
+                            .join(conf.profile())
+                            .join(&lib_file),
+                        features,
+                        default_features,
+                    }
+                })
+                .collect::<Vec<_>>();
+            let library = match artifacts.as_slice() {
+                [artifact] => artifact.path.clone(),
+                _ => conf
+                    .build_dir
+                    .join("libs")
+                    .join(format!("{platform:?}"))
+                    .join(&lib_file),
+            };
+            let framework = crate::core::framework_path(&platform, &module_name, &frameworks_dir);
+            SlicePlan {
+                deployment_target: conf.deployment_target(&platform),
+                platform,
+                artifacts,
+                library,
+                framework,
+            }
+        })
+        .collect();
+
+    let output_name = conf.output_name(lib_type)?;
+    let output = conf.target_dir.join(format!("{output_name}.xcframework"));
+    let archives = conf
+        .cargo_section
+        .archive_formats()
+        .into_iter()
+        .map(|format| {
+            conf.target_dir
+                .join(format!("{output_name}.xcframework.{}", format.extension()))
+        })
+        .collect();
+
+    Ok(XCFrameworkPlan {
+        module_name,
+        lib_type: lib_type.clone(),
+        modulemap,
+        headers: get_header_paths(include_dir, warnings)?,
+        slices,
+        output,
+        archives,
+    })
+}
+
+pub(crate) fn get_header_paths(
+    include_dir: &Utf8PathBuf,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<Utf8PathBuf>> {
+    let mut header_paths = Vec::new();
+    let pattern = format!("{}/**/*.h", include_dir);
+
+    for entry in glob::glob(&pattern)? {
+        match entry {
+            Ok(path) => header_paths.push(Utf8PathBuf::from_path_buf(path).unwrap()),
+            Err(e) => warnings.push(format!("skipping header: {e}")),
+        }
+    }
+
+    Ok(header_paths)
+}
+
+pub(crate) fn get_module_path(include_dir: &Utf8PathBuf) -> Result<Utf8PathBuf> {
+    let pattern = format!("{include_dir}/**/*.modulemap");
+    let mut glob = glob::glob(&pattern)?;
+    let module_path = glob.next().context("modulemap not found")??;
+    if glob.next().is_some() {
+        anyhow::bail!("multiple modulemaps found");
+    }
+
+    Ok(Utf8PathBuf::from_path_buf(module_path).unwrap())
+}
+
+impl fmt::Display for BuildPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(variant) = &self.variant {
+            writeln!(f, "Variant: {variant}")?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "Warning: {warning}")?;
+        }
+        writeln!(f, "Cargo")?;
+        for command in &self.cargo {
+            writeln!(f, "  {command}")?;
+        }
+        for xcframework in &self.xcframeworks {
+            write!(f, "{xcframework}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for XCFrameworkPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lib_type = match self.lib_type {
+            LibType::StaticLib => "staticlib",
+            LibType::CDyLib => "cdylib",
+        };
+        writeln!(f, "{} ({lib_type})", self.module_name)?;
+        for slice in &self.slices {
+            writeln!(f, "  {}", slice.platform.platform_display_name())?;
+            for artifact in &slice.artifacts {
+                writeln!(f, "    {}: {}", artifact.target, artifact.path)?;
+                let mut features = artifact.features.clone();
+                if artifact.default_features {
+                    features.insert(0, "default".into());
+                }
+                if features.is_empty() {
+                    writeln!(f, "      features: none")?;
+                } else {
+                    writeln!(f, "      features: {}", features.join(", "))?;
+                }
+            }
+            if slice.artifacts.len() > 1 {
+                writeln!(f, "    lipo: {}", slice.library)?;
+            }
+            writeln!(f, "    deployment target: {}", slice.deployment_target)?;
+            writeln!(f, "    framework: {}", slice.framework)?;
+        }
+        writeln!(f, "  modulemap: {}", self.modulemap)?;
+        for header in &self.headers {
+            writeln!(f, "  header: {header}")?;
+        }
+        writeln!(f, "  output: {}", self.output)?;
+        for archive in &self.archives {
+            writeln!(f, "  archive: {archive}")?;
+        }
+        Ok(())
+    }
+}
@@ -1,23 +1,34 @@
 use anyhow::Result;
 use camino_fs::*;
 use std::process::Command;
-use xcframework::CliArgs;
+use xcframework::{CliArgs, LibType};
 
 fn args(vec: &[&str]) -> CliArgs {
     CliArgs::from_vec(vec.iter().map(|s| s.into()).collect()).unwrap()
 }
 #[test]
 fn test_hello() {
-    // FIXME: if needed targets missing, it will block running the test by interactive prompt
-    // WORKAROUND: config `rust-toolchain.toml`, prepare the targets fisrt
-
-    let cli = args(&["--quiet", "--manifest-path", "tests/project/Cargo.toml"]);
+    let cli = args(&[
+        "--quiet",
+        "--install-targets",
+        "--manifest-path",
+        "tests/project/Cargo.toml",
+    ]);
 
-    let produced = xcframework::build_from_cli(cli).unwrap();
-    assert!(!produced.is_zipped);
+    let produced = xcframework::build_from_cli(cli).unwrap().remove(0);
+    assert!(produced.archives.is_empty());
     assert_eq!(produced.module_name, "HelloTest");
 }
 
+#[test]
+fn cli_args_parse_the_build_arguments() {
+    let cli = args(&["--release", "--lib-type", "staticlib"]);
+    assert!(cli.release);
+
+    let verify = CliArgs::from_vec(vec!["verify".into(), "My.xcframework".into()]);
+    assert!(verify.is_err());
+}
+
 fn create_output_dir(subfolder: &str) -> Utf8PathBuf {
     let tmp_dir = Utf8PathBuf::from("tests").join("temp").join(subfolder);
     tmp_dir.rm().unwrap();
@@ -42,8 +53,8 @@ fn end_to_end_static() {
         target_dir.as_str(),
     ]);
 
-    let produced = xcframework::build_from_cli(cli).unwrap();
-    assert!(!produced.is_zipped);
+    let produced = xcframework::build_from_cli(cli).unwrap().remove(0);
+    assert!(produced.archives.is_empty());
     assert_eq!(produced.module_name, "MyMath");
 
     let swift_dir = cp_swift_exe(&out_dir).unwrap();
@@ -78,8 +89,8 @@ fn end_to_end_dynamic() {
         target_dir.as_str(),
     ]);
 
-    let produced = xcframework::build_from_cli(cli).unwrap();
-    assert!(!produced.is_zipped);
+    let produced = xcframework::build_from_cli(cli).unwrap().remove(0);
+    assert!(produced.archives.is_empty());
     assert_eq!(produced.module_name, "MyMath");
 
     let swift_dir = cp_swift_exe(out_dir.as_path()).unwrap();
@@ -95,6 +106,98 @@ fn end_to_end_dynamic() {
     assert_eq!("MyMath.rust_add(4 + 2) = 6\n", output);
 }
 
+#[test]
+fn end_to_end_both() {
+    let out_dir = create_output_dir("both");
+
+    let target_dir = out_dir.join("mymath-lib/target");
+    target_dir.mkdirs().unwrap();
+
+    let cli = args(&[
+        "--quiet",
+        "--manifest-path",
+        "examples/end-to-end/mymath-lib/Cargo.toml",
+        "--lib-type",
+        "both",
+        "--target-dir",
+        target_dir.as_str(),
+    ]);
+
+    let produced = xcframework::build_from_cli(cli).unwrap();
+    let names = produced
+        .iter()
+        .map(|p| p.module_name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["MyMath", "MyMathDynamic"]);
+    assert_eq!(produced[0].lib_type, LibType::StaticLib);
+    assert!(target_dir.join("MyMath.xcframework").is_dir());
+    assert!(target_dir.join("MyMathDynamic.xcframework").is_dir());
+}
+
+#[test]
+fn dry_run_plan() {
+    let conf = xcframework::ConfigurationBuilder::new()
+        .manifest_path("examples/end-to-end/mymath-lib/Cargo.toml")
+        .lib_types([LibType::StaticLib, LibType::CDyLib])
+        .release()
+        .output_dir("tests/temp/plan")
+        .build()
+        .unwrap();
+    let plan = xcframework::plan(&conf).unwrap();
+
+    let [cargo] = plan.cargo.as_slice() else {
+        panic!("expected a single cargo invocation");
+    };
+    assert!(cargo.args.contains(&"--release".to_string()));
+    assert!(
+        cargo
+            .args
+            .contains(&"--target=aarch64-apple-ios-sim".to_string())
+    );
+
+    let [staticlib, cdylib] = plan.xcframeworks.as_slice() else {
+        panic!("expected two xcframeworks");
+    };
+    assert_eq!(staticlib.module_name, "MyMath");
+    assert_eq!(cdylib.module_name, "MyMathDynamic");
+    assert_eq!(cdylib.output, "tests/temp/plan/MyMathDynamic.xcframework");
+    assert_eq!(
+        cdylib.modulemap,
+        "tests/temp/plan/xcframework/MyMathDynamic.modulemap"
+    );
+
+    let platforms = staticlib
+        .slices
+        .iter()
+        .map(|s| s.platform.platform_display_name())
+        .collect::<Vec<_>>();
+    assert_eq!(platforms, ["iOS", "iOS Simulator", "macOS"]);
+    let ios = &staticlib.slices[0];
+    assert_eq!(
+        ios.library,
+        "tests/temp/plan/aarch64-apple-ios/release/libmymath.a"
+    );
+    assert!(ios.artifacts[0].features.is_empty());
+    assert!(ios.artifacts[0].default_features);
+    let simulator = &staticlib.slices[1];
+    assert_eq!(simulator.artifacts.len(), 2);
+    assert_eq!(
+        simulator.library,
+        "tests/temp/plan/xcframework/libs/IOS(Simulator)/libmymath.a"
+    );
+    assert!(staticlib.headers.iter().any(|h| h.ends_with("mymath.h")));
+
+    let json = serde_json::to_value(&plan).unwrap();
+    assert_eq!(
+        json["xcframeworks"][1]["slices"][2]["platform"],
+        serde_json::json!("macOS")
+    );
+    assert!(
+        plan.to_string()
+            .contains("lipo: tests/temp/plan/xcframework/libs")
+    );
+}
+
 #[test]
 #[ignore = "FIXME: not work on CI"]
 fn multi_platform_static() {
@@ -109,7 +212,7 @@ fn multi_platform_static() {
         "--target-dir",
         target_dir.as_str(),
     ]);
-    let produced = xcframework::build_from_cli(cli).unwrap();
+    let produced = xcframework::build_from_cli(cli).unwrap().remove(0);
     assert_eq!(produced.module_name, "MyMath");
     let tuist_workspace_dir = cp_tuist_workspace(out_dir.as_path()).unwrap();
     let cmd = Command::new("tuist")
@@ -139,7 +242,7 @@ fn multi_platform_dynamic() {
         "--target-dir",
         target_dir.as_str(),
     ]);
-    let produced = xcframework::build_from_cli(cli).unwrap();
+    let produced = xcframework::build_from_cli(cli).unwrap().remove(0);
     assert_eq!(produced.module_name, "MyMath");
     let tuist_workspace_dir = cp_tuist_workspace(out_dir.as_path()).unwrap();
     let cmd = Command::new("tuist")